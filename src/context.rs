@@ -0,0 +1,119 @@
+use async_trait::async_trait;
+
+use crate::runner::JobRunner;
+use crate::store::JobStore;
+use crate::{Reaction, ReplyTo};
+
+/// Everything handling a command or a status update talks to. Tests swap these for fakes.
+#[derive(Clone, Copy)]
+pub(crate) struct Context<'a> {
+    pub(crate) runner: &'a dyn JobRunner,
+    pub(crate) store: &'a dyn JobStore,
+    pub(crate) replies: &'a dyn Replies,
+    /// See [`MAINTAINERS`](crate::MAINTAINERS).
+    pub(crate) maintainers: &'a [String],
+}
+
+/// The context of the running bot, as configured by the environment.
+pub(crate) fn global() -> Context<'static> {
+    Context {
+        runner: &**crate::RUNNER,
+        store: &**crate::JOB_STORE,
+        replies: &ChatReplies,
+        maintainers: &crate::MAINTAINERS,
+    }
+}
+
+/// Posting to GitHub issues and Zulip threads.
+#[async_trait]
+pub(crate) trait Replies: Send + Sync {
+    /// See [`ReplyTo::comment`].
+    async fn comment(&self, reply_to: &ReplyTo, body: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// See [`ReplyTo::edit`].
+    async fn edit(&self, reply_to: &ReplyTo, comment_id: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// See [`ReplyTo::react`].
+    async fn react(&self, reply_to: &ReplyTo, comment_id: &str, reaction: Reaction) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Whether the bot is allowed to post in `reply_to` at all.
+    async fn can_reply(&self, reply_to: &ReplyTo) -> bool;
+}
+
+/// Replies using the GitHub and Zulip APIs.
+pub(crate) struct ChatReplies;
+
+#[async_trait]
+impl Replies for ChatReplies {
+    async fn comment(&self, reply_to: &ReplyTo, body: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        reply_to.comment(body).await
+    }
+
+    async fn edit(&self, reply_to: &ReplyTo, comment_id: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        reply_to.edit(comment_id, body).await
+    }
+
+    async fn react(&self, reply_to: &ReplyTo, comment_id: &str, reaction: Reaction) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        reply_to.react(comment_id, reaction).await
+    }
+
+    async fn can_reply(&self, reply_to: &ReplyTo) -> bool {
+        match reply_to {
+            ReplyTo::Github { repo, .. } => crate::github_auth::can_act_on(repo).await,
+            ReplyTo::ZulipPublic { .. } | ReplyTo::ZulipPrivate { .. } | ReplyTo::ZulipGroupPrivate { .. } => true,
+        }
+    }
+}
+
+/// Something the bot sent, as recorded by [`FakeReplies`].
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Reply {
+    Comment(ReplyTo, String),
+    Edit(ReplyTo, String, String),
+    React(ReplyTo, String, Reaction),
+}
+
+/// Replies which are only recorded. Comments get ids like `reply1`.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct FakeReplies {
+    pub(crate) sent: std::sync::Mutex<Vec<Reply>>,
+    /// Threads [`can_reply`](Replies::can_reply) refuses.
+    pub(crate) unreachable: Vec<ReplyTo>,
+}
+
+#[cfg(test)]
+impl FakeReplies {
+    /// The bodies of all comments posted so far.
+    pub(crate) fn comments(&self) -> Vec<String> {
+        self.sent.lock().unwrap().iter().filter_map(|reply| match reply {
+            Reply::Comment(_, body) => Some(body.clone()),
+            _ => None,
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Replies for FakeReplies {
+    async fn comment(&self, reply_to: &ReplyTo, body: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut sent = self.sent.lock().unwrap();
+        sent.push(Reply::Comment(reply_to.clone(), body.to_string()));
+        Ok(format!("reply{}", sent.len()))
+    }
+
+    async fn edit(&self, reply_to: &ReplyTo, comment_id: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.sent.lock().unwrap().push(Reply::Edit(reply_to.clone(), comment_id.to_string(), body.to_string()));
+        Ok(())
+    }
+
+    async fn react(&self, reply_to: &ReplyTo, comment_id: &str, reaction: Reaction) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.sent.lock().unwrap().push(Reply::React(reply_to.clone(), comment_id.to_string(), reaction));
+        Ok(())
+    }
+
+    async fn can_reply(&self, reply_to: &ReplyTo) -> bool {
+        !self.unreachable.contains(reply_to)
+    }
+}
@@ -0,0 +1,37 @@
+use crate::context::Context;
+use crate::store::JobTarget;
+
+/// Show `message` as the status of job `job_id` in all of `targets`.
+///
+/// Every target has a single status message, which is posted for the first update and edited for
+/// all later ones. The targets are updated concurrently and a failure to reach one of them doesn't
+/// keep the others from being updated.
+pub(crate) async fn dispatch(ctx: Context<'_>, job_id: &str, targets: &[JobTarget], message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let results = futures::future::join_all(targets.iter().map(|target| update_target(ctx, job_id, target, message))).await;
+    let mut failed = 0;
+    for (target, result) in targets.iter().zip(results) {
+        if let Err(err) = result {
+            println!("failed to report status of job {} to {:?}: {}", job_id, target.reply_to, err);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} reply targets failed", failed, targets.len()).into());
+    }
+    Ok(())
+}
+
+async fn update_target(ctx: Context<'_>, job_id: &str, target: &JobTarget, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(message_id) = &target.status_message {
+        match ctx.replies.edit(&target.reply_to, message_id, message).await {
+            Ok(()) => return Ok(()),
+            // The message may have been deleted, so post a new one
+            Err(err) => println!("failed to edit status message {} of job {}: {}", message_id, job_id, err),
+        }
+    }
+    let message_id = ctx.replies.comment(&target.reply_to, message).await?;
+    if let Err(err) = ctx.store.set_status_message(job_id, &target.reply_to, &message_id) {
+        println!("failed to record job {}: {}", job_id, err);
+    }
+    Ok(())
+}
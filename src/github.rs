@@ -1,9 +1,37 @@
-use hyper::{Body, Request, Response};
+use hmac::{Hmac, Mac};
+use hyper::{Body, Request, Response, StatusCode};
+use sha2::Sha256;
 
 pub(crate) async fn web_hook(req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
     let event = req.headers().get("X-GitHub-Event").ok_or("no X-Github-Event header")?.to_str()?.to_string();
+    let delivery = req.headers().get("X-GitHub-Delivery").map(|delivery| delivery.to_str().map(|delivery| delivery.to_string())).transpose()?;
+    let signature = req.headers().get("X-Hub-Signature-256").map(|sig| sig.to_str().map(|sig| sig.to_string())).transpose()?;
     let body: hyper::body::Bytes = hyper::body::to_bytes(req.into_body()).await?;
-    let body = std::str::from_utf8(&*body)?;
+    if let Err(err) = verify_signature(crate::GITHUB_WEBHOOK_SECRET.as_bytes(), &body, signature.as_deref()) {
+        println!("rejected {} event: {}", event, err);
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(err.into())?);
+    }
+    // Redelivered events keep their delivery id
+    if let Some(delivery) = &delivery {
+        if !crate::JOB_STORE.first_delivery(delivery)? {
+            println!("ignoring duplicate delivery {} of {} event", delivery, event);
+            return Ok(Response::new("duplicate delivery".into()));
+        }
+    }
+    let res = handle_event(&event, &body).await;
+    if let (Err(_), Some(delivery)) = (&res, &delivery) {
+        // Allow GitHub to redeliver the event
+        if let Err(err) = crate::JOB_STORE.forget_delivery(delivery) {
+            println!("failed to forget delivery {}: {}", delivery, err);
+        }
+    }
+    res
+}
+
+async fn handle_event(event: &str, body: &[u8]) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let body = std::str::from_utf8(body)?;
     let json: serde_json::Value = serde_json::from_str(body)?;
 
     let repo = json
@@ -12,54 +40,37 @@ pub(crate) async fn web_hook(req: Request<Body>) -> Result<Response<Body>, Box<d
         .get("repository")
         .and_then(|repo| repo.as_object()?.get("full_name")?.as_str())
         .ok_or("missing repo")?;
-    if !crate::REPO_WHITELIST.iter().any(|&r| r == repo) {
+    if !crate::REPO_WHITELIST.contains(&repo) {
         println!("wrong repo {:?}", json);
         return Ok(Response::new("wrong repo".into()));
     }
 
-    match &*event {
+    match event {
         "issue_comment" => {
             let event: IssueCommentEvent = serde_json::from_value(json)?;
 
-            if event.action != "created" {
-                return Ok(Response::new("processed".into()));
+            let edited = match &*event.action {
+                "created" => false,
+                "edited" => true,
+                _ => return Ok(Response::new("processed".into())),
+            };
+            println!("{:?} {} \"{}\"", event.sender.login, if edited { "edited" } else { "commented" }, event.comment.body);
+            // Never act on replies of the bot itself or of other bots
+            if event.comment.user.type_ == "Bot" || crate::GITHUB_AUTH.is_own_login(&event.comment.user.login) {
+                return Ok(Response::new("ignored bot comment".into()));
             }
-            println!("{:?} commented \"{}\"", event.sender.login, event.comment.body);
             crate::parse_comment(
+                crate::context::global(),
                 &crate::ReplyTo::Github { repo: event.repository.full_name.clone(), issue_number: event.issue.number },
+                crate::job::Requester::Github { login: event.comment.user.login.clone() },
                 &format!("gh{}", event.comment.id),
                 &event.comment.body,
+                edited,
             ).await?;
         }
         "check_run" => {
-            let event: CheckRunEvent = serde_json::from_value(json)?;
-            println!("check_run action: {}", event.action);
-            let reply_to = {
-                let res = gh_api(&format!(
-                    "https://api.github.com/repos/{}/git/commits/{}",
-                    crate::JOB_REPO, event.check_run.head_sha,
-                )).await?;
-                let commit: Commit = serde_json::from_str(&res)?;
-                crate::ReplyTo::from_commit_message(&commit.message).map_err(|()| format!("Failed to parse commit message {:?}", commit.message))?
-            };
-            println!("reply to: {:?}", reply_to);
-            match &*event.action {
-                "created" => {
-                    reply_to.comment(&format!(
-                        "bisection job {} started: {}",
-                        event.check_run.id, event.check_run.html_url,
-                    )).await?;
-                }
-                "completed" => {
-                    reply_to.comment(&format!(
-                        "bisection job {} completed: {}",
-                        event.check_run.id, event.check_run.html_url,
-                    )).await?;
-                }
-                _ => {
-                    println!("unknown check_run action");
-                }
-            }
+            let res = crate::runner::github_actions::handle_check_run(json).await?;
+            return Ok(Response::new(res.into()));
         }
         _ => {
             println!("unknown event {}: {}", event, body);
@@ -70,14 +81,66 @@ pub(crate) async fn web_hook(req: Request<Body>) -> Result<Response<Body>, Box<d
     Ok(Response::new("processed".into()))
 }
 
-#[derive(serde::Deserialize)]
-struct Repository {
-    full_name: String,
+/// The rust-lang/rust PR that merged `sha`.
+pub(crate) async fn merged_pr(sha: &str) -> Option<u64> {
+    match gh_api(crate::JOB_REPO, &format!("https://api.github.com/repos/rust-lang/rust/commits/{}", sha)).await {
+        Ok(res) => serde_json::from_str::<serde_json::Value>(&res).ok()
+            .and_then(|commit| crate::report::merged_pr(commit["commit"]["message"].as_str()?)),
+        Err(err) => {
+            println!("failed to fetch rust-lang/rust commit {}: {}", sha, err);
+            None
+        }
+    }
+}
+
+/// Check the `X-Hub-Signature-256` header against the HMAC-SHA256 of the request body.
+fn verify_signature(secret: &[u8], body: &[u8], signature: Option<&str>) -> Result<(), &'static str> {
+    let signature = signature.ok_or("missing signature")?;
+    let signature = signature.strip_prefix("sha256=").ok_or("unsupported signature algorithm")?;
+    let signature = hex::decode(signature).map_err(|_| "malformed signature")?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| "invalid webhook secret")?;
+    mac.update(body);
+    // `verify_slice` does a constant time comparison
+    mac.verify_slice(&signature).map_err(|_| "signature mismatch")
+}
+
+#[test]
+fn test_verify_signature() {
+    // Example from https://docs.github.com/en/webhooks/using-webhooks/validating-webhook-deliveries
+    assert_eq!(
+        verify_signature(
+            b"It's a Secret to Everybody",
+            b"Hello, World!",
+            Some("sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"),
+        ),
+        Ok(()),
+    );
+
+    let payload = br#"{"action":"created","issue":{"number":1},"comment":{"id":622441717,"body":"bisect-bot bisect end=2020-05-01\n```rust\nfn main() {}\n```"},"repository":{"full_name":"bjorn3/cargo-bisect-rustc-bot"},"sender":{"login":"bjorn3"}}"#;
+    let secret = b"bisect-bot test secret";
+    assert_eq!(
+        verify_signature(secret, payload, Some("sha256=4c1cf1a89fc3018b32919adf6f8b2bbe64ed6ba46562393222de6442205c6d4e")),
+        Ok(()),
+    );
+    assert_eq!(
+        verify_signature(b"wrong secret", payload, Some("sha256=4c1cf1a89fc3018b32919adf6f8b2bbe64ed6ba46562393222de6442205c6d4e")),
+        Err("signature mismatch"),
+    );
+    assert_eq!(
+        verify_signature(secret, &payload[1..], Some("sha256=4c1cf1a89fc3018b32919adf6f8b2bbe64ed6ba46562393222de6442205c6d4e")),
+        Err("signature mismatch"),
+    );
+    assert_eq!(verify_signature(secret, payload, None), Err("missing signature"));
+    assert_eq!(
+        verify_signature(secret, payload, Some("sha1=2fd4e1c67a2d28fced849ee1bb76e7391b93eb12")),
+        Err("unsupported signature algorithm"),
+    );
+    assert_eq!(verify_signature(secret, payload, Some("sha256=xyz")), Err("malformed signature"));
 }
 
 #[derive(serde::Deserialize)]
-struct Commit {
-    message: String,
+pub(crate) struct Repository {
+    pub(crate) full_name: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -89,11 +152,17 @@ struct Issue {
 struct Comment {
     id: u64,
     body: String,
+    /// The author of the comment. This differs from the sender of the event when someone else
+    /// edited the comment.
+    user: User,
 }
 
 #[derive(serde::Deserialize)]
 struct User {
     login: String,
+    /// `User` or `Bot`. Comments posted through a GitHub App are authored by its `Bot` user.
+    #[serde(rename = "type", default)]
+    type_: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -105,66 +174,120 @@ struct IssueCommentEvent {
     sender: User,
 }
 
-#[derive(serde::Deserialize)]
-struct CheckRun {
-    id: u64,
-    head_sha: String,
-    html_url: String,
-}
-
-#[derive(serde::Deserialize)]
-struct CheckRunEvent {
-    action: String,
-    check_run: CheckRun,
-    repository: Repository,
-}
-
-async fn gh_api(url: &str) -> reqwest::Result<String> {
+pub(crate) async fn gh_api(repo: &str, url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     println!("GET {}", url);
-    let res: reqwest::Response = reqwest::Client::new()
+    let req = reqwest::Client::new()
         .get(url)
         .header(hyper::http::header::USER_AGENT, hyper::http::HeaderValue::from_str(crate::USER_AGENT).unwrap())
-        .header(hyper::http::header::ACCEPT, hyper::http::HeaderValue::from_str("application/vnd.github.antiope-preview+json").unwrap())
-        .basic_auth(&*crate::GITHUB_USERNAME, Some(&*crate::GITHUB_TOKEN))
+        .header(hyper::http::header::ACCEPT, hyper::http::HeaderValue::from_str("application/vnd.github.antiope-preview+json").unwrap());
+    let res: reqwest::Response = crate::github_auth::authorize(req, repo).await?
         .send()
         .await?;
     println!("GET {}: {}", url, res.status());
     match res.error_for_status_ref() {
-        Ok(_) => res.text().await,
+        Ok(_) => Ok(res.text().await?),
         Err(err) => {
             println!("{}", res.text().await?);
-            return Err(err)
+            Err(err.into())
         }
     }
 }
 
-pub(crate) async fn gh_api_post(url: &str, body: String) -> reqwest::Result<String> {
+pub(crate) async fn gh_api_post(repo: &str, url: &str, body: String) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     println!("POST {} <- {}", url, body);
-    let res = reqwest::Client::new()
+    let req = reqwest::Client::new()
         .post(url)
         .header(hyper::http::header::USER_AGENT, hyper::http::HeaderValue::from_str(crate::USER_AGENT).unwrap())
         .header(hyper::http::header::ACCEPT, hyper::http::HeaderValue::from_str("application/vnd.github.v3.html+json").unwrap())
-        .header(hyper::http::header::CONTENT_TYPE, hyper::http::HeaderValue::from_str("text/json").unwrap())
-        .basic_auth(&*crate::GITHUB_USERNAME, Some(&*crate::GITHUB_TOKEN))
+        .header(hyper::http::header::CONTENT_TYPE, hyper::http::HeaderValue::from_str("text/json").unwrap());
+    let res = crate::github_auth::authorize(req, repo).await?
         .body(body)
         .send()
         .await?;
     println!("POST {}: {}", url, res.status());
     match res.error_for_status_ref() {
-        Ok(_) => res.text().await,
+        Ok(_) => Ok(res.text().await?),
+        Err(err) => {
+            println!("{}", res.text().await?);
+            Err(err.into())
+        }
+    }
+}
+
+pub(crate) async fn gh_api_patch(repo: &str, url: &str, body: String) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    println!("PATCH {} <- {}", url, body);
+    let req = reqwest::Client::new()
+        .patch(url)
+        .header(hyper::http::header::USER_AGENT, hyper::http::HeaderValue::from_str(crate::USER_AGENT).unwrap())
+        .header(hyper::http::header::ACCEPT, hyper::http::HeaderValue::from_str("application/vnd.github.v3.html+json").unwrap())
+        .header(hyper::http::header::CONTENT_TYPE, hyper::http::HeaderValue::from_str("text/json").unwrap());
+    let res = crate::github_auth::authorize(req, repo).await?
+        .body(body)
+        .send()
+        .await?;
+    println!("PATCH {}: {}", url, res.status());
+    match res.error_for_status_ref() {
+        Ok(_) => Ok(res.text().await?),
+        Err(err) => {
+            println!("{}", res.text().await?);
+            Err(err.into())
+        }
+    }
+}
+
+pub(crate) async fn gh_api_delete(repo: &str, url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!("DELETE {}", url);
+    let req = reqwest::Client::new()
+        .delete(url)
+        .header(hyper::http::header::USER_AGENT, hyper::http::HeaderValue::from_str(crate::USER_AGENT).unwrap());
+    let res = crate::github_auth::authorize(req, repo).await?
+        .send()
+        .await?;
+    println!("DELETE {}: {}", url, res.status());
+    match res.error_for_status_ref() {
+        Ok(_) => Ok(()),
         Err(err) => {
             println!("{}", res.text().await?);
-            return Err(err)
+            Err(err.into())
         }
     }
 }
 
-pub(crate) async fn gh_post_comment(repo: &str, issue_number: u64, body: &str) -> reqwest::Result<()> {
+/// Post a comment, returning its id.
+pub(crate) async fn gh_post_comment(repo: &str, issue_number: u64, body: &str) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
     println!("on issue {} post comment {:?}", issue_number, body);
-    let _res = gh_api_post(
+    let res = gh_api_post(
+        repo,
         &format!("https://api.github.com/repos/{}/issues/{}/comments", repo, issue_number),
-        format!(r#"{{"body": {:?}}}"#, body),
+        serde_json::json!({ "body": body }).to_string(),
+    ).await?;
+    let comment: CreatedComment = serde_json::from_str(&res)?;
+    println!("on issue {} post comment {} success", issue_number, comment.id);
+    Ok(comment.id)
+}
+
+pub(crate) async fn gh_edit_comment(repo: &str, comment_id: u64, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!("edit comment {} to {:?}", comment_id, body);
+    gh_api_patch(
+        repo,
+        &format!("https://api.github.com/repos/{}/issues/comments/{}", repo, comment_id),
+        serde_json::json!({ "body": body }).to_string(),
+    ).await?;
+    Ok(())
+}
+
+/// The part of a new comment we care about. The body isn't included, as the comment is rendered
+/// as html.
+#[derive(serde::Deserialize)]
+struct CreatedComment {
+    id: u64,
+}
+
+pub(crate) async fn gh_add_reaction(repo: &str, comment_id: u64, content: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    gh_api_post(
+        repo,
+        &format!("https://api.github.com/repos/{}/issues/comments/{}/reactions", repo, comment_id),
+        serde_json::json!({ "content": content }).to_string(),
     ).await?;
-    println!("on issue {} post comment success", issue_number);
     Ok(())
 }
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// How the bot authenticates against the GitHub API.
+pub(crate) enum GithubAuth {
+    /// Basic auth with the personal access token of a single user.
+    PersonalAccessToken {
+        username: String,
+        token: String,
+    },
+    /// Authenticate as a GitHub App using per-installation access tokens.
+    App {
+        app_id: String,
+        key: jsonwebtoken::EncodingKey,
+    },
+}
+
+impl GithubAuth {
+    /// Use GitHub App authentication when `GITHUB_APP_ID` is set, otherwise fall back to a
+    /// personal access token.
+    pub(crate) fn from_env() -> Self {
+        match std::env::var("GITHUB_APP_ID") {
+            Ok(app_id) => {
+                let key = match std::env::var("GITHUB_APP_PRIVATE_KEY") {
+                    Ok(key) => key,
+                    Err(_) => {
+                        let path = std::env::var("GITHUB_APP_PRIVATE_KEY_PATH").expect("github app private key not defined");
+                        std::fs::read_to_string(path).expect("failed to read github app private key")
+                    }
+                };
+                GithubAuth::App {
+                    app_id,
+                    key: jsonwebtoken::EncodingKey::from_rsa_pem(key.as_bytes()).expect("invalid github app private key"),
+                }
+            }
+            Err(_) => GithubAuth::PersonalAccessToken {
+                username: std::env::var("GITHUB_USERNAME").expect("github username not defined"),
+                token: std::env::var("GITHUB_TOKEN").expect("github personal access token not defined"),
+            },
+        }
+    }
+
+    /// Whether `login` is the GitHub user the bot posts as. Comments of a GitHub App are made by
+    /// its `Bot` user, which is recognized by the user type instead.
+    pub(crate) fn is_own_login(&self, login: &str) -> bool {
+        match self {
+            GithubAuth::PersonalAccessToken { username, .. } => username.eq_ignore_ascii_case(login),
+            GithubAuth::App { .. } => false,
+        }
+    }
+}
+
+/// Refresh installation tokens this long before GitHub expires them.
+const TOKEN_REFRESH_MARGIN_MINUTES: i64 = 5;
+
+struct InstallationToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl InstallationToken {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        now + Duration::minutes(TOKEN_REFRESH_MARGIN_MINUTES) < self.expires_at
+    }
+}
+
+#[derive(Default)]
+struct TokenCache {
+    /// Installation id for every repo we have talked to.
+    installations: HashMap<String, u64>,
+    /// Access token for every installation id.
+    tokens: HashMap<u64, InstallationToken>,
+}
+
+lazy_static::lazy_static! {
+    static ref TOKEN_CACHE: tokio::sync::Mutex<TokenCache> = Default::default();
+}
+
+/// Add the authentication for acting on `repo` to a request.
+pub(crate) async fn authorize(
+    req: reqwest::RequestBuilder,
+    repo: &str,
+) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error + Send + Sync>> {
+    match &*crate::GITHUB_AUTH {
+        GithubAuth::PersonalAccessToken { username, token } => Ok(req.basic_auth(username, Some(token))),
+        GithubAuth::App { app_id, key } => {
+            let token = installation_token(app_id, key, repo).await?;
+            Ok(req.header(hyper::http::header::AUTHORIZATION, format!("token {}", token)))
+        }
+    }
+}
+
+/// Whether the bot can act on `repo`. A GitHub App can only act on the repos it is installed on.
+pub(crate) async fn can_act_on(repo: &str) -> bool {
+    match &*crate::GITHUB_AUTH {
+        GithubAuth::PersonalAccessToken { .. } => true,
+        GithubAuth::App { app_id, key } => match installation_token(app_id, key, repo).await {
+            Ok(_) => true,
+            Err(err) => {
+                println!("github app not usable for {}: {}", repo, err);
+                false
+            }
+        },
+    }
+}
+
+/// Look up the installations for all repos the bot works with, so that a misconfigured app is
+/// noticed at startup rather than when the first job arrives.
+pub(crate) async fn init() {
+    if let GithubAuth::App { app_id, key } = &*crate::GITHUB_AUTH {
+        for &repo in crate::REPO_WHITELIST {
+            match installation_token(app_id, key, repo).await {
+                Ok(_) => println!("github app installed on {}", repo),
+                Err(err) => println!("github app not usable for {}: {}", repo, err),
+            }
+        }
+    }
+}
+
+fn app_jwt(app_id: &str, key: &jsonwebtoken::EncodingKey) -> Result<String, jsonwebtoken::errors::Error> {
+    #[derive(serde::Serialize)]
+    struct Claims<'a> {
+        iat: i64,
+        exp: i64,
+        iss: &'a str,
+    }
+
+    let now = Utc::now();
+    let claims = Claims {
+        // Allow for some clock drift between us and GitHub
+        iat: (now - Duration::seconds(60)).timestamp(),
+        // GitHub accepts at most 10 minutes
+        exp: (now + Duration::minutes(9)).timestamp(),
+        iss: app_id,
+    };
+    jsonwebtoken::encode(&jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256), &claims, key)
+}
+
+async fn installation_token(
+    app_id: &str,
+    key: &jsonwebtoken::EncodingKey,
+    repo: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut cache = TOKEN_CACHE.lock().await;
+
+    let installation_id = match cache.installations.get(repo) {
+        Some(&id) => id,
+        None => {
+            #[derive(serde::Deserialize)]
+            struct Installation {
+                id: u64,
+            }
+
+            let res = app_api(
+                reqwest::Client::new().get(&format!("https://api.github.com/repos/{}/installation", repo)),
+                app_id,
+                key,
+            ).await?;
+            let installation: Installation = serde_json::from_str(&res)?;
+            println!("github app installation for {}: {}", repo, installation.id);
+            cache.installations.insert(repo.to_string(), installation.id);
+            installation.id
+        }
+    };
+
+    if let Some(token) = cache.tokens.get(&installation_id) {
+        if token.is_fresh(Utc::now()) {
+            return Ok(token.token.clone());
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    struct AccessToken {
+        token: String,
+        expires_at: DateTime<Utc>,
+    }
+
+    let res = app_api(
+        reqwest::Client::new().post(&format!("https://api.github.com/app/installations/{}/access_tokens", installation_id)),
+        app_id,
+        key,
+    ).await?;
+    let access_token: AccessToken = serde_json::from_str(&res)?;
+    println!("refreshed token for installation {}, expires at {}", installation_id, access_token.expires_at);
+    cache.tokens.insert(installation_id, InstallationToken {
+        token: access_token.token.clone(),
+        expires_at: access_token.expires_at,
+    });
+    Ok(access_token.token)
+}
+
+async fn app_api(
+    req: reqwest::RequestBuilder,
+    app_id: &str,
+    key: &jsonwebtoken::EncodingKey,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let res = req
+        .header(hyper::http::header::USER_AGENT, crate::USER_AGENT)
+        .header(hyper::http::header::ACCEPT, "application/vnd.github.machine-man-preview+json")
+        .bearer_auth(app_jwt(app_id, key)?)
+        .send()
+        .await?;
+    match res.error_for_status_ref() {
+        Ok(_) => Ok(res.text().await?),
+        Err(err) => {
+            println!("{}", res.text().await?);
+            Err(err.into())
+        }
+    }
+}
+
+#[test]
+fn test_installation_token_refresh() {
+    let now = Utc::now();
+    let token = |expires_in| InstallationToken {
+        token: "v1.abc".to_string(),
+        expires_at: now + Duration::minutes(expires_in),
+    };
+    assert!(token(60).is_fresh(now));
+    assert!(!token(TOKEN_REFRESH_MARGIN_MINUTES).is_fresh(now));
+    assert!(!token(1).is_fresh(now));
+    assert!(!token(-1).is_fresh(now));
+}
@@ -0,0 +1,322 @@
+use chrono::{DateTime, Utc};
+
+use crate::command::BisectOptions;
+use crate::ReplyTo;
+
+/// Name of the job specification file in the root of the job tree.
+pub(crate) const JOB_FILE: &str = "bisect-job.json";
+
+/// Version of the job specification format. Bump this on incompatible changes.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// Manifest of the crate the repro is built as.
+pub(crate) const CARGO_TOML: &str = r#"[package]
+name = "cargo-bisect-bot-job"
+version = "0.0.0"
+edition = "2018"
+publish = false
+
+[dependencies]
+    "#;
+
+/// Name of the script which runs cargo-bisect-rustc with the arguments from [`JOB_FILE`] and writes
+/// `bisect-result.json`. The CI configs of all runners call it.
+pub(crate) const RUN_FILE: &str = "run-bisect.sh";
+
+/// Contents of [`RUN_FILE`].
+pub(crate) const RUN_SCRIPT: &str = r#"#!/usr/bin/env bash
+set -e
+
+# Everything is in a function, so bash has read the whole script before the user script runs and
+# could change this file
+main() {
+  jq -e '.schema_version == 1' bisect-job.json
+  mapfile -t args < <(jq -r '.args[]' bisect-job.json)
+  status=0
+  cargo bisect-rustc "${args[@]}" --access=github > bisect-stdout.log 2> bisect-stderr.log < /dev/null || status=$?
+  grep -v "for x86_64-unknown-linux-gnu" bisect-stdout.log || true
+  cat bisect-stderr.log >&2
+
+  nightlies=$(sed -n 's/^searched nightlies: from \(.*\) to \(.*\)$/\1 \2/p' bisect-stdout.log bisect-stderr.log | tail -n1)
+  toolchain=$(sed -n 's/^regressed nightly: //p' bisect-stdout.log bisect-stderr.log | tail -n1)
+  commits=$(sed -n 's/^searched commits: from \(.*\) to \(.*\)$/\1 \2/p' bisect-stdout.log bisect-stderr.log | tail -n1)
+  commit=$(sed -n 's|^regressed commit: https://github.com/rust-lang/rust/commit/||p' bisect-stdout.log bisect-stderr.log | tail -n1)
+  if [ $status -ne 0 ]; then
+    result=error
+  elif [ -n "$toolchain$commit" ]; then
+    result=found
+  else
+    result=not-found
+  fi
+  tail -n 50 bisect-stderr.log > bisect-stderr-tail.log
+  jq -n \
+    --arg status "$result" \
+    --argjson exit_code "$status" \
+    --arg nightlies "$nightlies" \
+    --arg toolchain "$toolchain" \
+    --arg commits "$commits" \
+    --arg commit "$commit" \
+    --rawfile stderr_tail bisect-stderr-tail.log \
+    '{
+      schema_version: 1,
+      status: $status,
+      exit_code: $exit_code,
+      searched_nightlies: (if $nightlies == "" then null else ($nightlies | split(" ")) end),
+      regressed_toolchain: (if $toolchain == "" then null else $toolchain end),
+      searched_commits: (if $commits == "" then null else ($commits | split(" ") | map(ltrimstr("https://github.com/rust-lang/rust/commit/"))) end),
+      commit: (if $commit == "" then null else $commit end),
+      stderr_tail: $stderr_tail
+    }' > bisect-result.json
+  cat bisect-result.json
+}
+main
+"#;
+
+/// Contents of `bisect.sh` for a user supplied script.
+pub(crate) fn script_file(script: &str) -> String {
+    format!("#!/usr/bin/env bash\n{}\n", script)
+}
+
+/// Who asked for a bisection.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub(crate) enum Requester {
+    Github {
+        login: String,
+    },
+    Zulip {
+        user_id: u64,
+        full_name: String,
+    },
+}
+
+impl Requester {
+    /// Identifies the user across jobs, like `github:bjorn3` or `zulip:123`. This is also the
+    /// format of the entries in `BOT_MAINTAINERS`.
+    pub(crate) fn key(&self) -> String {
+        match self {
+            // GitHub logins are case-insensitive
+            Requester::Github { login } => format!("github:{}", login.to_lowercase()),
+            Requester::Zulip { user_id, .. } => format!("zulip:{}", user_id),
+        }
+    }
+
+    /// Whether this user may cancel or otherwise manage a job requested by `owner`.
+    pub(crate) fn may_manage(&self, owner: &Requester, maintainers: &[String]) -> bool {
+        let key = self.key();
+        key == owner.key() || maintainers.contains(&key)
+    }
+
+    /// Whether this user may have the status of a job reported to `target`. Only maintainers may
+    /// notify issues outside of [`REPO_WHITELIST`](crate::REPO_WHITELIST).
+    pub(crate) fn may_notify(&self, target: &ReplyTo, maintainers: &[String]) -> bool {
+        match target {
+            ReplyTo::Github { repo, .. } if crate::REPO_WHITELIST.contains(&&**repo) => true,
+            _ => maintainers.contains(&self.key()),
+        }
+    }
+}
+
+impl std::fmt::Display for Requester {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            // Not `@login` to avoid pinging people every time a job is listed
+            Requester::Github { login } => write!(f, "{}", login),
+            Requester::Zulip { full_name, .. } => write!(f, "{}", full_name),
+        }
+    }
+}
+
+/// Everything needed to inspect or replay a bisection job. This is committed as [`JOB_FILE`] next
+/// to the repro and read by the bisect workflow.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct JobSpec {
+    pub(crate) schema_version: u32,
+    pub(crate) job_id: String,
+    /// The comment the job was requested in. Empty for jobs from before retries existed.
+    #[serde(default)]
+    pub(crate) comment_id: String,
+    /// Starts at 1 and is incremented every time the job for a comment is run again.
+    #[serde(default = "first_attempt")]
+    pub(crate) attempt: u32,
+    pub(crate) requester: Requester,
+    /// The thread the job was requested in.
+    pub(crate) reply_to: ReplyTo,
+    /// Other threads the status of the job is reported to.
+    #[serde(default)]
+    pub(crate) notify: Vec<ReplyTo>,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) options: BisectOptions,
+    /// Whether the job tree contains a `bisect.sh` to pass as `--script`.
+    pub(crate) script: bool,
+    /// The arguments for cargo-bisect-rustc, as rendered from `options` and `script`.
+    pub(crate) args: Vec<String>,
+}
+
+fn first_attempt() -> u32 {
+    1
+}
+
+/// The id of a job. Every attempt at running the job requested in a comment gets its own id, so
+/// that its branch doesn't collide with the one of an earlier attempt.
+pub(crate) fn job_id(comment_id: &str, attempt: u32) -> String {
+    if attempt <= 1 {
+        comment_id.to_string()
+    } else {
+        format!("{}-{}", comment_id, attempt)
+    }
+}
+
+impl JobSpec {
+    pub(crate) fn new(
+        comment_id: &str,
+        attempt: u32,
+        requester: Requester,
+        reply_to: ReplyTo,
+        notify: Vec<ReplyTo>,
+        options: BisectOptions,
+        script: bool,
+    ) -> Result<Self, String> {
+        let mut args = options.to_args();
+        if script {
+            args.push("--script=./bisect.sh".to_string());
+        }
+        // The workflow reads the arguments one per line
+        if let Some(arg) = args.iter().find(|arg| arg.contains(['\n', '\r', '\0'])) {
+            return Err(format!("invalid bisect argument {:?}", arg));
+        }
+
+        Ok(JobSpec {
+            schema_version: SCHEMA_VERSION,
+            job_id: job_id(comment_id, attempt),
+            comment_id: comment_id.to_string(),
+            attempt,
+            requester,
+            reply_to,
+            notify,
+            created_at: Utc::now(),
+            options,
+            script,
+            args,
+        })
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// A job as handed to a [`JobRunner`](crate::runner::JobRunner).
+#[derive(Debug, Clone)]
+pub(crate) struct Job {
+    pub(crate) spec: JobSpec,
+    /// Contents of `src/lib.rs`.
+    pub(crate) code: String,
+    /// Contents of `bisect.sh`, if `spec.script` is set.
+    pub(crate) script: Option<String>,
+}
+
+impl Job {
+    /// The files of the job tree except for the workflow, as `(path, contents, executable)`.
+    pub(crate) fn files(&self) -> Vec<(&'static str, String, bool)> {
+        let mut files = vec![
+            ("Cargo.toml", CARGO_TOML.to_string(), false),
+            ("src/lib.rs", self.code.clone(), false),
+            (JOB_FILE, self.spec.to_json(), false),
+            (RUN_FILE, RUN_SCRIPT.to_string(), true),
+        ];
+        if let Some(script) = &self.script {
+            files.push(("bisect.sh", script_file(script), true));
+        }
+        files
+    }
+
+    /// Hex encoded SHA-256 of the repro, to recognise jobs for the same code and script.
+    pub(crate) fn repro_hash(&self) -> String {
+        use sha2::Digest;
+
+        let mut hasher = sha2::Sha256::new();
+        for (path, contents, _) in self.files() {
+            // The job file differs between jobs for the same repro and the run script between
+            // versions of the bot
+            if path != JOB_FILE && path != RUN_FILE {
+                hasher.update(path);
+                hasher.update([0]);
+                hasher.update(contents);
+                hasher.update([0]);
+            }
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_job(job_id: &str) -> Job {
+    let options = BisectOptions {
+        end: crate::command::ToolchainSpec::parse("2020-05-01"),
+        ..BisectOptions::default()
+    };
+    Job {
+        spec: JobSpec::new(
+            job_id,
+            1,
+            Requester::Github { login: "bjorn3".to_string() },
+            ReplyTo::Github { repo: "a/b".to_string(), issue_number: 1 },
+            Vec::new(),
+            options,
+            false,
+        ).unwrap(),
+        code: "fn main() {}".to_string(),
+        script: None,
+    }
+}
+
+#[test]
+fn test_job_spec() {
+    let options = BisectOptions {
+        start: crate::command::ToolchainSpec::parse("2020-01-01"),
+        end: crate::command::ToolchainSpec::parse("1.43.0"),
+        regress: Some(crate::command::Regress::NonIce),
+        components: vec!["rust-src".to_string()],
+        ..BisectOptions::default()
+    };
+    let job = JobSpec::new(
+        "gh123",
+        2,
+        Requester::Github { login: "bjorn3".to_string() },
+        ReplyTo::ZulipPublic { stream_id: 123, subject: "a | b".to_string() },
+        vec![ReplyTo::Github { repo: "rust-lang/rust".to_string(), issue_number: 12345 }],
+        options,
+        true,
+    ).unwrap();
+    assert_eq!(
+        job.args,
+        ["--start=2020-01-01", "--end=1.43.0", "--regress=non-ice", "--component=rust-src", "--script=./bisect.sh"],
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&job.to_json()).unwrap();
+    assert_eq!(json["schema_version"], 1);
+    assert_eq!(json["job_id"], "gh123-2");
+    assert_eq!(json["requester"], serde_json::json!({ "type": "github", "login": "bjorn3" }));
+    assert_eq!(json["reply_to"], serde_json::json!({ "type": "zulip-public", "stream_id": 123, "subject": "a | b" }));
+    assert_eq!(json["notify"], serde_json::json!([{ "type": "github", "repo": "rust-lang/rust", "issue_number": 12345 }]));
+    assert_eq!(json["options"]["start"], "2020-01-01");
+    assert_eq!(json["options"]["regress"], "non-ice");
+
+    assert_eq!(serde_json::from_str::<JobSpec>(&job.to_json()).unwrap(), job);
+}
+
+#[test]
+fn test_may_manage() {
+    let owner = Requester::Github { login: "bjorn3".to_string() };
+    let maintainers = ["zulip:42".to_string()];
+    assert!(Requester::Github { login: "Bjorn3".to_string() }.may_manage(&owner, &maintainers));
+    assert!(Requester::Zulip { user_id: 42, full_name: "M".to_string() }.may_manage(&owner, &maintainers));
+    assert!(!Requester::Zulip { user_id: 43, full_name: "bjorn3".to_string() }.may_manage(&owner, &maintainers));
+    assert!(!Requester::Github { login: "someone".to_string() }.may_manage(&owner, &maintainers));
+
+    let issue = |repo: &str| ReplyTo::Github { repo: repo.to_string(), issue_number: 1 };
+    assert!(owner.may_notify(&issue("bjorn3/cargo-bisect-rustc-bot"), &maintainers));
+    assert!(!owner.may_notify(&issue("rust-lang/rust"), &maintainers));
+    assert!(Requester::Zulip { user_id: 42, full_name: "M".to_string() }.may_notify(&issue("rust-lang/rust"), &maintainers));
+}
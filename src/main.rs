@@ -1,29 +1,74 @@
 use std::convert::Infallible;
+use hmac::{Hmac, Mac};
 use hyper::{Body, Request, Response, Server};
+use sha2::Sha256;
 use hyper::service::{make_service_fn, service_fn};
 
+use crate::command::Command;
+use crate::context::Context;
+use crate::job::{Job, JobSpec, Requester};
+use crate::runner::JobStatus;
+
+mod command;
+mod context;
+mod dispatcher;
 mod github;
+mod github_auth;
+mod job;
+mod report;
+mod runner;
+mod store;
 mod zulip;
 
-const BOT_NAME: &'static str = "bisect-bot ";
-const USER_AGENT: &'static str = "https://github.com/bjorn3/cargo-bisect-rustc-bot";
-const REPO_WHITELIST: &'static [&'static str] = &["bjorn3/cargo-bisect-rustc-bot", JOB_REPO];
-const JOB_REPO: &'static str = "bjorn3/cargo-bisect-rustc-bot-jobs";
+const BOT_NAME: &str = "bisect-bot ";
+const USER_AGENT: &str = "https://github.com/bjorn3/cargo-bisect-rustc-bot";
+const REPO_WHITELIST: &[&str] = &["bjorn3/cargo-bisect-rustc-bot", JOB_REPO];
+const JOB_REPO: &str = "bjorn3/cargo-bisect-rustc-bot-jobs";
 lazy_static::lazy_static! {
-    static ref GITHUB_USERNAME: String = std::env::var("GITHUB_USERNAME").expect("github username not defined");
-    static ref GITHUB_TOKEN: String = std::env::var("GITHUB_TOKEN").expect("github personal access token not defined");
+    static ref GITHUB_AUTH: crate::github_auth::GithubAuth = crate::github_auth::GithubAuth::from_env();
+    static ref GITHUB_WEBHOOK_SECRET: String = std::env::var("GITHUB_WEBHOOK_SECRET").expect("github webhook secret not defined");
+    /// Key used to sign the [`ReplyTo`] commit header.
+    static ref REPLY_TO_SECRET: String = match std::env::var("REPLY_TO_SECRET") {
+        Ok(secret) => secret,
+        Err(_) if cfg!(test) => "bisect-bot test secret".to_string(),
+        Err(_) => panic!("reply to secret not defined"),
+    };
+    /// Accept reply to headers without signature, as pushed by older versions of the bot. Only
+    /// enable this until the jobs pushed before upgrading have finished.
+    static ref ALLOW_UNSIGNED_REPLY_TO: bool = std::env::var("ALLOW_UNSIGNED_REPLY_TO").is_ok_and(|allow| allow == "1");
+    static ref RUNNER: Box<dyn crate::runner::JobRunner> = crate::runner::from_env();
+    static ref JOB_STORE: Box<dyn crate::store::JobStore> = crate::store::from_env();
+    /// Users who may manage all jobs, as comma separated [`Requester::key`]s.
+    static ref MAINTAINERS: Vec<String> = std::env::var("BOT_MAINTAINERS")
+        .map(|maintainers| maintainers.split(',').map(|m| m.trim().to_lowercase()).filter(|m| !m.is_empty()).collect())
+        .unwrap_or_default();
     static ref ZULIP_USER: String = std::env::var("ZULIP_USERNAME").expect("zulip username not defined");
     static ref ZULIP_TOKEN: String = std::env::var("ZULIP_TOKEN").expect("zulip api token not defined");
 }
 
 #[tokio::main]
 async fn main() {
+    if std::env::args().nth(1).as_deref() == Some("worker") {
+        // Run jobs leased from a bot using the worker runner instead of serving webhooks
+        let server = std::env::var("WORKER_SERVER").expect("worker server not defined");
+        let token = std::env::var("WORKER_TOKEN").expect("worker token not defined");
+        let name = std::env::var("WORKER_NAME").unwrap_or_else(|_| "worker".to_string());
+        let executor = crate::runner::local::LocalRunner::from_env(crate::runner::ignore_updates());
+        crate::runner::worker::run_worker(server.trim_end_matches('/'), &token, &name, &executor).await;
+        return;
+    }
+
+    // Fail on startup rather than on the first event when the job database or runner isn't
+    // configured. This also requeues the jobs which were running before a restart.
+    lazy_static::initialize(&JOB_STORE);
+    lazy_static::initialize(&RUNNER);
+    crate::github_auth::init().await;
     let _zulip = tokio::spawn(crate::zulip::zulip_task());
 
     let addr = (
         [0, 0, 0, 0],
         std::env::var("PORT")
-            .unwrap_or("3000".to_string())
+            .unwrap_or_else(|_| "3000".to_string())
             .parse::<u16>()
             .unwrap(),
     )
@@ -42,13 +87,18 @@ async fn main() {
 }
 
 async fn request_handler(req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    crate::github::web_hook(req).await.map_err(|err| {
+    let res = match RUNNER.http_prefix() {
+        Some(prefix) if req.uri().path().starts_with(prefix) => RUNNER.handle_request(req).await,
+        _ => crate::github::web_hook(req).await,
+    };
+    res.map_err(|err| {
         println!("error: {}", err);
         err
     })
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
 enum ReplyTo {
     Github {
         repo: String,
@@ -61,357 +111,723 @@ enum ReplyTo {
     ZulipPrivate {
         user_id: u64,
     },
+    /// A private conversation with several users, not counting the bot.
+    ZulipGroupPrivate {
+        /// Sorted, so every conversation has a single representation.
+        user_ids: Vec<u64>,
+    },
 }
 
 impl ReplyTo {
-    async fn comment(&self, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// Post a comment or message in this thread. The returned id can be passed to [`edit`](Self::edit).
+    async fn comment(&self, body: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         match *self {
             ReplyTo::Github { ref repo, issue_number } => {
-                crate::github::gh_post_comment(repo, issue_number, body).await?;
-                Ok(())
+                let comment_id = crate::github::gh_post_comment(repo, issue_number, body).await?;
+                Ok(format!("gh{}", comment_id))
             }
             ReplyTo::ZulipPublic { stream_id, ref subject } => {
-                crate::zulip::zulip_post_public_message(stream_id, subject, body).await
+                let message_id = crate::zulip::zulip_post_public_message(stream_id, subject, body).await?;
+                Ok(format!("zulip{}", message_id))
             }
             ReplyTo::ZulipPrivate { user_id } => {
-                crate::zulip::zulip_post_private_message(user_id, body).await
+                let message_id = crate::zulip::zulip_post_private_message(&[user_id], body).await?;
+                Ok(format!("zulip{}", message_id))
+            }
+            ReplyTo::ZulipGroupPrivate { ref user_ids } => {
+                let message_id = crate::zulip::zulip_post_private_message(user_ids, body).await?;
+                Ok(format!("zulip{}", message_id))
             }
         }
     }
 
-    const COMMIT_HEADER: &'static str = "X-Bisectbot-Reply-To";
-
-    fn to_commit_header(&self) -> String {
+    /// Replace the contents of the comment or message `comment_id` in this thread.
+    async fn edit(&self, comment_id: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         match *self {
-            ReplyTo::Github { ref repo, issue_number } => {
-                format!("{}: github {}#{}", Self::COMMIT_HEADER, repo, issue_number)
+            ReplyTo::Github { ref repo, .. } => {
+                let comment_id = comment_id.strip_prefix("gh").ok_or("not a github comment")?.parse()?;
+                crate::github::gh_edit_comment(repo, comment_id, body).await
             }
-            ReplyTo::ZulipPublic { stream_id, ref subject } => {
-                format!("{}: zulip-public {} | {}", Self::COMMIT_HEADER, stream_id, subject)
+            ReplyTo::ZulipPublic { .. } | ReplyTo::ZulipPrivate { .. } | ReplyTo::ZulipGroupPrivate { .. } => {
+                let message_id = comment_id.strip_prefix("zulip").ok_or("not a zulip message")?.parse()?;
+                crate::zulip::zulip_edit_message(message_id, body).await
             }
-            ReplyTo::ZulipPrivate { user_id } => {
-                format!("{}: zulip-private {}", Self::COMMIT_HEADER, user_id)
+        }
+    }
+
+    /// React to the comment or message `comment_id` in this thread.
+    async fn react(&self, comment_id: &str, reaction: Reaction) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match *self {
+            ReplyTo::Github { ref repo, .. } => {
+                let comment_id = comment_id.strip_prefix("gh").ok_or("not a github comment")?.parse()?;
+                crate::github::gh_add_reaction(repo, comment_id, reaction.github_content()).await
+            }
+            ReplyTo::ZulipPublic { .. } | ReplyTo::ZulipPrivate { .. } | ReplyTo::ZulipGroupPrivate { .. } => {
+                let message_id = comment_id.strip_prefix("zulip").ok_or("not a zulip message")?.parse()?;
+                crate::zulip::zulip_add_reaction(message_id, reaction.zulip_emoji_name()).await
             }
         }
     }
 
-    fn from_commit_message(message: &str) -> Result<Self, ()> {
+    const COMMIT_HEADER: &str = "X-Bisectbot-Reply-To";
+
+    /// The commit header telling where to report the status of job `job_id`.
+    ///
+    /// The header is signed with `REPLY_TO_SECRET`, so people who can push to the job repo can't
+    /// make the bot post elsewhere. The target is encoded as `v2 <base64 of the JSON>`, so new
+    /// variants and arbitrary Zulip subjects don't need changes to the format.
+    fn to_commit_header(&self, job_id: &str) -> String {
+        let json = serde_json::to_string(self).expect("ReplyTo can always be serialized");
+        let target = format!("v2 {}", base64::encode(json));
+        let signature = reply_to_signature(REPLY_TO_SECRET.as_bytes(), job_id, &target).finalize().into_bytes();
+        format!("{}: sha256={} {}", Self::COMMIT_HEADER, hex::encode(signature), target)
+    }
+
+    /// Parse the header written by [`to_commit_header`](Self::to_commit_header) for job `job_id`.
+    /// Headers without signature are only accepted if `allow_unsigned` is set.
+    fn from_commit_message(message: &str, job_id: &str, allow_unsigned: bool) -> Result<Self, &'static str> {
         for line in message.lines() {
-            let line = line.trim();
-            if !line.starts_with(Self::COMMIT_HEADER) {
-                continue;
-            }
-            let header = line[Self::COMMIT_HEADER.len()+1..].trim();
-            let mut split = header.split(" ");
-            let kind = split.next().ok_or(())?.trim();
-            let to = split.next().ok_or(())?.trim();
-            match kind {
-                "github" => {
-                    if split.next().is_some() {
-                        return Err(());
-                    }
-                    let mut split = to.split("#");
-                    let repo = split.next().ok_or(())?.trim();
-                    let issue_number = split.next().ok_or(())?.trim().parse().map_err(|_| ())?;
-                    if split.next().is_some() {
-                        return Err(());
-                    }
-                    return Ok(ReplyTo::Github {
-                        repo: repo.to_string(),
-                        issue_number,
-                    });
+            let header = match line.trim().strip_prefix(Self::COMMIT_HEADER).and_then(|header| header.strip_prefix(':')) {
+                Some(header) => header.trim(),
+                None => continue,
+            };
+            let target = match header.strip_prefix("sha256=") {
+                Some(header) => {
+                    let (signature, target) = header.split_once(' ').ok_or("malformed header")?;
+                    let signature = hex::decode(signature).map_err(|_| "malformed signature")?;
+                    // `verify_slice` does a constant time comparison
+                    reply_to_signature(REPLY_TO_SECRET.as_bytes(), job_id, target)
+                        .verify_slice(&signature)
+                        .map_err(|_| "signature mismatch")?;
+                    target
                 }
-                "zulip-public" => {
-                    let stream_id: u64 = to.parse().map_err(|_| ())?;
-                    let subject = header[header.find("|").ok_or(())?+2..].to_string();
-                    return Ok(ReplyTo::ZulipPublic {
-                        stream_id,
-                        subject,
-                    })
+                None if allow_unsigned => header,
+                None => return Err("missing signature"),
+            };
+            return match target.split_once(' ') {
+                Some(("v2", json)) => {
+                    let json = base64::decode(json).map_err(|_| "malformed header")?;
+                    serde_json::from_slice(&json).map_err(|_| "unknown reply target")
                 }
-                "zulip-private" => {
-                    if split.next().is_some() {
-                        return Err(());
-                    }
-                    let user_id = to.parse().map_err(|_| ())?;
-                    return Ok(ReplyTo::ZulipPrivate {
-                        user_id,
-                    });
+                Some((version, _)) if version.strip_prefix('v').is_some_and(|n| n.parse::<u32>().is_ok()) => {
+                    Err("unsupported header version")
                 }
-                _ => return Err(()),
-            }
+                _ => Self::from_v1_target(target),
+            };
         }
 
-        Err(())
+        Err("missing header")
     }
+
+    /// Parse the target as written by versions of the bot before the encoding was versioned.
+    fn from_v1_target(target: &str) -> Result<Self, &'static str> {
+        let mut split = target.split(' ');
+        let kind = split.next().ok_or("malformed header")?.trim();
+        let to = split.next().ok_or("malformed header")?.trim();
+        match kind {
+            "github" => {
+                if split.next().is_some() {
+                    return Err("malformed header");
+                }
+                let mut split = to.split('#');
+                let repo = split.next().ok_or("malformed header")?.trim();
+                let issue_number = split.next().ok_or("malformed header")?.trim().parse().map_err(|_| "malformed header")?;
+                if split.next().is_some() {
+                    return Err("malformed header");
+                }
+                Ok(ReplyTo::Github {
+                    repo: repo.to_string(),
+                    issue_number,
+                })
+            }
+            "zulip-public" => {
+                let stream_id: u64 = to.parse().map_err(|_| "malformed header")?;
+                let (_, subject) = target.split_once(" | ").ok_or("malformed header")?;
+                let subject = subject.to_string();
+                Ok(ReplyTo::ZulipPublic {
+                    stream_id,
+                    subject,
+                })
+            }
+            "zulip-private" => {
+                if split.next().is_some() {
+                    return Err("malformed header");
+                }
+                let user_id = to.parse().map_err(|_| "malformed header")?;
+                Ok(ReplyTo::ZulipPrivate {
+                    user_id,
+                })
+            }
+            _ => Err("unknown reply target"),
+        }
+    }
+}
+
+/// HMAC-SHA256 binding a reply target to the job it belongs to, so a signed header can't be copied
+/// to another job either.
+fn reply_to_signature(secret: &[u8], job_id: &str, target: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(job_id.as_bytes());
+    mac.update(b"\n");
+    mac.update(target.as_bytes());
+    mac
 }
 
 #[test]
 fn test_reply_to_parsing() {
     assert_eq!(
-        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: github a/b#5"),
+        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: github a/b#5", "gh1", true),
         Ok(ReplyTo::Github { repo: "a/b".to_string(), issue_number: 5}),
     );
     assert_eq!(
-        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: zulip-public 123 | this is the #1 topic on this zulip instance!"),
+        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: zulip-public 123 | this is the #1 topic on this zulip instance!", "gh1", true),
         Ok(ReplyTo::ZulipPublic { stream_id: 123, subject: "this is the #1 topic on this zulip instance!".to_string() }),
     );
     assert_eq!(
-        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: zulip-private 123"),
+        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: zulip-private 123", "gh1", true),
         Ok(ReplyTo::ZulipPrivate { user_id: 123 }),
     );
+    assert_eq!(ReplyTo::from_commit_message("X-Bisectbot-Reply-To: zulip-public 123 |", "gh1", true), Err("malformed header"));
+    // Signed headers from before the encoding was versioned
+    let signature = reply_to_signature(REPLY_TO_SECRET.as_bytes(), "gh1", "github a/b#5").finalize().into_bytes();
+    assert_eq!(
+        ReplyTo::from_commit_message(&format!("X-Bisectbot-Reply-To: sha256={} github a/b#5", hex::encode(signature)), "gh1", false),
+        Ok(ReplyTo::Github { repo: "a/b".to_string(), issue_number: 5}),
+    );
 }
 
-enum Command {
-    Bisect {
-        start: Option<String>,
-        end: String,
-        code: String,
-    },
+#[test]
+fn test_reply_to_signature() {
+    let reply_to = ReplyTo::ZulipPublic { stream_id: 123, subject: "a | b".to_string() };
+    let header = reply_to.to_commit_header("zulip1");
+    let message = format!("Bisect job for comment id zulip1\n\n{}", header);
+    assert_eq!(ReplyTo::from_commit_message(&message, "zulip1", false), Ok(reply_to));
+    assert_eq!(ReplyTo::from_commit_message(&message, "zulip2", false), Err("signature mismatch"));
+
+    // A valid signature for a different target
+    let signature = header.split(' ').nth(1).unwrap();
+    let other_target = ReplyTo::ZulipPrivate { user_id: 1 }.to_commit_header("zulip1");
+    let forged = format!("X-Bisectbot-Reply-To: {} {}", signature, other_target.splitn(3, ' ').nth(2).unwrap());
+    assert_eq!(ReplyTo::from_commit_message(&forged, "zulip1", false), Err("signature mismatch"));
+
+    assert_eq!(
+        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: sha256=xyz zulip-private 1", "zulip1", false),
+        Err("malformed signature"),
+    );
+    assert_eq!(
+        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: zulip-private 1", "zulip1", false),
+        Err("missing signature"),
+    );
+    assert_eq!(ReplyTo::from_commit_message("Bisect job", "zulip1", true), Err("missing header"));
+    assert_eq!(
+        ReplyTo::from_commit_message("X-Bisectbot-Reply-To: v3 e30=", "zulip1", true),
+        Err("unsupported header version"),
+    );
 }
 
-impl Command {
-    fn parse_comment(comment: &str) -> Result<Option<Command>, String> {
-        let mut lines = comment.lines();
-        while let Some(line) = lines.next() {
-            let line = line.trim();
-            if !line.starts_with(BOT_NAME) {
-                continue;
-            }
-            let line = line[BOT_NAME.len()..].trim();
-            let mut parts = line.split(" ").map(|part| part.trim());
-
-            match parts.next() {
-                Some("bisect") => {
-                    let mut start = None;
-                    let mut end = None;
-                    for part in parts {
-                        if part.starts_with("start=") {
-                            if start.is_some() {
-                                return Err(format!("start range specified twice"));
-                            }
-                            start = Some(part["start=".len()..].to_string());
-                        } else if part.starts_with("end=") {
-                            if end.is_some() {
-                                return Err(format!("end range specified twice"));
-                            }
-                            end = Some(part["end=".len()..].to_string());
-                        } else {
-                            return Err(format!("unknown command part {:?}", part));
-                        }
-                    }
-                    let end = end.ok_or("missing end range")?;
-                    loop {
-                        match lines.next() {
-                            Some(line) if line.trim() == "```rust" => break,
-                            Some(_) => {}
-                            None => {
-                                return Err("didn't find repro code".to_string());
-                            }
-                        }
-                    }
-                    let code = lines.take_while(|line| line.trim() != "```").collect::<Vec<_>>().join("\n");
-                    return Ok(Some(Command::Bisect {
-                        start,
-                        end,
-                        code,
-                    }));
-                }
-                cmd => {
-                    return Err(format!("unknown command {:?}", cmd));
-                }
+#[cfg(test)]
+fn arb_reply_to() -> impl proptest::strategy::Strategy<Value = ReplyTo> {
+    use proptest::prelude::*;
+    prop_oneof![
+        (".*", any::<u64>()).prop_map(|(repo, issue_number)| ReplyTo::Github { repo, issue_number }),
+        (any::<u64>(), ".*").prop_map(|(stream_id, subject)| ReplyTo::ZulipPublic { stream_id, subject }),
+        any::<u64>().prop_map(|user_id| ReplyTo::ZulipPrivate { user_id }),
+        proptest::collection::btree_set(any::<u64>(), 2..10)
+            .prop_map(|user_ids| ReplyTo::ZulipGroupPrivate { user_ids: user_ids.into_iter().collect() }),
+    ]
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_reply_to_header_roundtrip(reply_to in arb_reply_to(), job_id in "[a-z0-9-]+") {
+        let message = format!("Bisect job for comment id {}\n\n{}\n", job_id, reply_to.to_commit_header(&job_id));
+        proptest::prop_assert_eq!(ReplyTo::from_commit_message(&message, &job_id, false), Ok(reply_to));
+    }
+}
+
+const USAGE: &str = r#"Usage:
+
+> bisect-bot bisect start=2020-01-01 end=2020-02-01 regress=ice --preserve
+> ```rust
+> // code reproducing the regression
+> ```
+
+`start` is optional, `end` is required. Both accept a nightly date (`YYYY-MM-DD`), a commit hash,
+a version number like `1.43.0` or a channel name.
+Values containing spaces can be quoted like `key="some value"`.
+
+Other options: `regress=error|success|ice|non-ice|non-error`, `target=<triple>`, `host=<triple>`,
+`component=<name>` (can be repeated), `timeout=<seconds>` and the flags `--preserve`, `--by-commit`,
+`--alt`, `--with-cargo` and `--with-src`. A ```` ```sh ```` block after the repro code is used as
+`--script`. `notify=rust-lang/rust#12345` (can be repeated) reports the status of the job to
+another issue too. Only maintainers can notify issues outside of the repositories of the bot.
+
+`bisect-bot status` lists the jobs started from or reported to this thread, `bisect-bot queue` lists
+all queued and running jobs. `bisect-bot cancel [job]` stops a job and `bisect-bot retry [job]` runs it again, by
+default the last one started from this thread. `bisect-bot subscribe <job>` reports the status of
+a job started elsewhere to this thread too."#;
+
+#[test]
+fn test_usage_is_not_a_command() {
+    // Replies of the bot must never contain a command in case they are ever read back.
+    assert_eq!(Command::parse_comment(&format!("Error: unknown command\n\n{}", USAGE)), Ok(None));
+}
+
+/// How the bot reacts to commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reaction {
+    /// The command was understood.
+    Seen,
+    /// The job was handed to the runner.
+    Pushed,
+    /// Something went wrong. This comes with an error reply.
+    Failed,
+}
+
+impl Reaction {
+    /// GitHub only supports a fixed set of reactions.
+    fn github_content(self) -> &'static str {
+        match self {
+            Reaction::Seen => "eyes",
+            Reaction::Pushed => "rocket",
+            // There is no cross mark on GitHub
+            Reaction::Failed => "confused",
+        }
+    }
+
+    fn zulip_emoji_name(self) -> &'static str {
+        match self {
+            Reaction::Seen => "eyes",
+            Reaction::Pushed => "rocket",
+            Reaction::Failed => "cross_mark",
+        }
+    }
+}
+
+/// React to the command in `comment_id`. A missing reaction isn't worth failing the command for,
+/// so errors are only logged.
+async fn react(ctx: Context<'_>, reply_to: &ReplyTo, comment_id: &str, reaction: Reaction) {
+    if let Err(err) = ctx.replies.react(reply_to, comment_id, reaction).await {
+        println!("failed to react with {:?} to {}: {}", reaction, comment_id, err);
+    }
+}
+
+/// Mark the command in `comment_id` as failed and explain why.
+async fn reply_error(ctx: Context<'_>, reply_to: &ReplyTo, comment_id: &str, message: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    react(ctx, reply_to, comment_id, Reaction::Failed).await;
+    ctx.replies.comment(reply_to, &format!("Error: {}", message)).await?;
+    Ok(())
+}
+
+/// Record a new job and hand it to the runner. `reply_to` and `comment_id` identify the command
+/// which started the job.
+async fn start_job(ctx: Context<'_>, job: Job, reply_to: &ReplyTo, comment_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let job_id = &job.spec.job_id;
+    println!("{:?}", &job.spec.args);
+    if let Err(err) = ctx.store.insert(&job) {
+        println!("failed to record job {}: {}", job_id, err);
+    }
+    match ctx.runner.submit(&job).await {
+        Ok(()) => {
+            react(ctx, reply_to, comment_id, Reaction::Pushed).await;
+            // This posts the status message, which is edited as the job progresses
+            crate::runner::job_updated(ctx, job_id, &job.spec.reply_to, JobStatus::Queued).await?;
+        }
+        Err(err) => {
+            println!("failed to push job {}: {}", job_id, err);
+            if let Err(err) = ctx.store.transition(job_id, crate::store::JobState::Failed, None) {
+                println!("failed to record job {}: {}", job_id, err);
             }
+            let message = match err.downcast_ref::<crate::runner::Rejected>() {
+                Some(crate::runner::Rejected(message)) => message,
+                None => "the bisection job could not be queued. Please try again later.",
+            };
+            reply_error(ctx, reply_to, comment_id, message).await?;
         }
+    }
+    Ok(())
+}
 
-        return Ok(None);
+/// Stop a job which is replaced by a new attempt.
+async fn supersede(ctx: Context<'_>, job: &crate::store::JobRecord) {
+    let job_id = &job.spec.job_id;
+    if !job.state.is_active() {
+        return;
+    }
+    if let Err(err) = ctx.runner.cancel(job_id).await {
+        println!("failed to cancel job {}: {}", job_id, err);
+    }
+    if let Err(err) = crate::runner::job_updated(ctx, job_id, &job.spec.reply_to, JobStatus::Cancelled).await {
+        println!("failed to report status of job {}: {}", job_id, err);
     }
 }
 
-async fn parse_comment(reply_to: &ReplyTo, comment_id: &str, comment: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    match Command::parse_comment(comment)? {
-        Some(Command::Bisect {
-            start,
-            end,
+/// Handle a comment or chat message. `edited` is set when an existing comment was edited, in which
+/// case a changed bisect command replaces the job of the previous version.
+async fn parse_comment(ctx: Context<'_>, reply_to: &ReplyTo, requester: Requester, comment_id: &str, comment: &str, edited: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let command = match Command::parse_comment(comment) {
+        Ok(Some(command)) => command,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            println!("invalid command in {}: {}", comment_id, err);
+            reply_error(ctx, reply_to, comment_id, &format!("{}\n\n{}", err, USAGE)).await?;
+            return Ok(());
+        }
+    };
+
+    // Only bisections are redone. Repeating the other commands isn't useful.
+    if edited && !matches!(command, Command::Bisect { .. }) {
+        return Ok(());
+    }
+    react(ctx, reply_to, comment_id, Reaction::Seen).await;
+
+    if let Err(err) = run_command(ctx, reply_to, requester, comment_id, command, edited).await {
+        // Usually the store failed. The requester should know the command wasn't handled.
+        println!("failed to handle command {}: {}", comment_id, err);
+        reply_error(ctx, reply_to, comment_id, "something went wrong while handling the command. Please try again later.").await?;
+    }
+    Ok(())
+}
+
+/// Carry out a command of `requester`.
+async fn run_command(ctx: Context<'_>, reply_to: &ReplyTo, requester: Requester, comment_id: &str, command: Command, edited: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match command {
+        Command::Bisect {
+            options,
+            mut notify,
             code,
-        }) => {
-            let mut cmds = Vec::new();
-            if let Some(start) = start {
-                cmds.push(format!("--start={}", start));
+            script,
+        } => {
+            // Posting to arbitrary issues would make the bot a spam vector
+            if notify.iter().any(|target| !requester.may_notify(target, ctx.maintainers)) {
+                let message = format!("only maintainers can notify issues outside of {}.", REPO_WHITELIST.join(", "));
+                reply_error(ctx, reply_to, comment_id, &message).await?;
+                return Ok(());
+            }
+            // A GitHub App can only post to the repos it is installed on
+            for target in &notify {
+                if !ctx.replies.can_reply(target).await {
+                    let message = match target {
+                        ReplyTo::Github { repo, issue_number } => format!("the bot can't post to {}#{}, it isn't installed there.", repo, issue_number),
+                        _ => "the bot can't post to one of the threads to notify.".to_string(),
+                    };
+                    reply_error(ctx, reply_to, comment_id, &message).await?;
+                    return Ok(());
+                }
+            }
+            let attempts = ctx.store.attempts(comment_id)?;
+            // Webhooks and Zulip events may be delivered more than once
+            if attempts > 0 && !edited {
+                println!("ignoring duplicate request {}", comment_id);
+                return Ok(());
+            }
+            let previous = match attempts {
+                0 => None,
+                _ => Some(ctx.store.get(&crate::job::job_id(comment_id, attempts))?.ok_or("previous attempt not found")?),
+            };
+            // Threads which subscribed to the previous attempt keep getting updates
+            if let Some(previous) = &previous {
+                for target in previous.extra_targets() {
+                    if !previous.spec.notify.contains(&target) && !notify.contains(&target) {
+                        notify.push(target);
+                    }
+                }
+            }
+            let spec = match JobSpec::new(comment_id, attempts + 1, requester, reply_to.clone(), notify, options, script.is_some()) {
+                Ok(spec) => spec,
+                Err(err) => {
+                    println!("invalid job for {}: {}", comment_id, err);
+                    reply_error(ctx, reply_to, comment_id, &err.to_string()).await?;
+                    return Ok(());
+                }
+            };
+            let job = Job { spec, code, script };
+            if let Some(previous) = previous {
+                if previous.repro_hash == job.repro_hash() && previous.spec.args == job.spec.args {
+                    println!("edit of {} didn't change the bisection", comment_id);
+                    // The edit may have added issues to notify
+                    for target in &job.spec.notify {
+                        ctx.store.add_target(&previous.spec.job_id, target)?;
+                    }
+                    return Ok(());
+                }
+                supersede(ctx, &previous).await;
+                ctx.replies.comment(reply_to, &format!(
+                    "The command was edited. Bisection job `{}` replaces `{}`.",
+                    job.spec.job_id, previous.spec.job_id,
+                )).await?;
+            }
+            start_job(ctx, job, reply_to, comment_id).await?;
+        }
+        Command::Retry { job_id } => {
+            let job = match job_id {
+                Some(job_id) => ctx.store.get(&job_id)?,
+                None => ctx.store.jobs_for(reply_to, 10)?.into_iter().find(|job| job.spec.reply_to == *reply_to),
+            };
+            let job = match job {
+                Some(job) => job,
+                None => {
+                    reply_error(ctx, reply_to, comment_id, "no bisection job to retry was found.").await?;
+                    return Ok(());
+                }
+            };
+            let job_id = &job.spec.job_id;
+            if !requester.may_manage(&job.spec.requester, ctx.maintainers) {
+                println!("{} may not retry job {}", requester.key(), job_id);
+                reply_error(ctx, reply_to, comment_id, &format!(
+                    "only {} or a maintainer can retry bisection job `{}`.",
+                    job.spec.requester, job_id,
+                )).await?;
+                return Ok(());
             }
-            cmds.push(format!("--end={}", end));
-            println!("{:?}", &cmds);
-            push_job(&reply_to, comment_id, &cmds, &code).await?;
+            supersede(ctx, &job).await;
+            let attempt = ctx.store.attempts(&job.spec.comment_id)? + 1;
+            let spec = match JobSpec::new(
+                &job.spec.comment_id,
+                attempt,
+                job.spec.requester.clone(),
+                job.spec.reply_to.clone(),
+                job.extra_targets(),
+                job.spec.options.clone(),
+                job.script.is_some(),
+            ) {
+                Ok(spec) => spec,
+                Err(err) => {
+                    reply_error(ctx, reply_to, comment_id, &err.to_string()).await?;
+                    return Ok(());
+                }
+            };
+            ctx.replies.comment(reply_to, &format!("Retrying bisection job `{}` as `{}`.", job_id, spec.job_id)).await?;
+            start_job(ctx, Job { spec, code: job.code, script: job.script }, reply_to, comment_id).await?;
+        }
+        Command::Subscribe { job_id } => {
+            let job = match ctx.store.get(&job_id)? {
+                Some(job) => job,
+                None => {
+                    reply_error(ctx, reply_to, comment_id, &format!("bisection job `{}` was not found.", job_id)).await?;
+                    return Ok(());
+                }
+            };
+            if !job.state.is_active() {
+                reply_error(ctx, reply_to, comment_id, &format!("bisection job `{}` already finished.", job_id)).await?;
+                return Ok(());
+            }
+            if ctx.store.add_target(&job_id, reply_to)? {
+                ctx.replies.comment(reply_to, &format!("The status of bisection job `{}` will be reported here too.", job_id)).await?;
+            } else {
+                ctx.replies.comment(reply_to, &format!("The status of bisection job `{}` is already reported here.", job_id)).await?;
+            }
+        }
+        Command::Status => {
+            let now = chrono::Utc::now();
+            let jobs = ctx.store.jobs_for(reply_to, 10)?;
+            if jobs.is_empty() {
+                ctx.replies.comment(reply_to, "No bisection jobs were started from or reported to here.").await?;
+                return Ok(());
+            }
+            let mut message = "Bisection jobs started from or reported to here, newest first:\n".to_string();
+            for job in jobs {
+                // Ask the runner for jobs which may have progressed without us noticing
+                let status = match job.state {
+                    crate::store::JobState::Queued | crate::store::JobState::Running => ctx.runner.status(&job.spec.job_id).await.ok(),
+                    _ => None,
+                };
+                message.push_str(&format!("\n{}", job.describe(now, status.as_ref())));
+            }
+            ctx.replies.comment(reply_to, &message).await?;
+        }
+        Command::Cancel { job_id } => {
+            let job = match job_id {
+                Some(job_id) => ctx.store.get(&job_id)?,
+                None => ctx.store.jobs_for(reply_to, 10)?.into_iter().find(|job| job.spec.reply_to == *reply_to && job.state.is_active()),
+            };
+            let job = match job {
+                Some(job) => job,
+                None => {
+                    reply_error(ctx, reply_to, comment_id, "no bisection job to cancel was found.").await?;
+                    return Ok(());
+                }
+            };
+            let job_id = &job.spec.job_id;
+            if !job.state.is_active() {
+                reply_error(ctx, reply_to, comment_id, &format!("bisection job `{}` already finished.", job_id)).await?;
+                return Ok(());
+            }
+            if !requester.may_manage(&job.spec.requester, ctx.maintainers) {
+                println!("{} may not cancel job {}", requester.key(), job_id);
+                reply_error(ctx, reply_to, comment_id, &format!(
+                    "only {} or a maintainer can cancel bisection job `{}`.",
+                    job.spec.requester, job_id,
+                )).await?;
+                return Ok(());
+            }
+            if let Err(err) = ctx.runner.cancel(job_id).await {
+                println!("failed to cancel job {}: {}", job_id, err);
+                reply_error(ctx, reply_to, comment_id, &format!("bisection job `{}` could not be cancelled. Please try again later.", job_id)).await?;
+                return Ok(());
+            }
+            if let Err(err) = crate::runner::job_updated(ctx, job_id, &job.spec.reply_to, JobStatus::Cancelled).await {
+                println!("failed to report status of job {}: {}", job_id, err);
+            }
+            ctx.replies.comment(reply_to, &format!("Cancelled bisection job `{}`.", job_id)).await?;
+        }
+        Command::Queue => {
+            let now = chrono::Utc::now();
+            let jobs = ctx.store.active_jobs()?;
+            let mut message = match jobs.len() {
+                0 => "There are no queued or running bisection jobs.".to_string(),
+                1 => "There is 1 queued or running bisection job:\n".to_string(),
+                n => format!("There are {} queued or running bisection jobs:\n", n),
+            };
+            for job in jobs {
+                message.push_str(&format!("\n{}", job.describe(now, None)));
+            }
+            ctx.replies.comment(reply_to, &message).await?;
         }
-        None => {}
     }
 
     Ok(())
 }
 
-async fn push_job(reply_to: &ReplyTo, job_id: &str, bisect_cmds: &[String], repro: &str) -> reqwest::Result<()> {
-    // Escape commands and join with whitespace
-    let bisect_cmds = bisect_cmds.iter().map(|cmd| format!("{:?}", cmd)).collect::<Vec<_>>().join(" ");
-
-    let src_lib = create_blob(repro).await?;
-    let src = create_tree(&[TreeEntry {
-        path: "lib.rs".to_string(),
-        mode: TreeEntryMode::File,
-        type_: TreeEntryType::Blob,
-        sha: src_lib,
-    }]).await?;
-
-    let github_workflow_bisect = create_blob(&format!(
-        r#"
-name: Bisect
-
-on:
-  - push
-
-jobs:
-  build:
-    runs-on: ubuntu-latest
-
-    steps:
-    - uses: actions/checkout@v2
-
-    - name: Cache cargo installed crates
-      uses: actions/cache@v1.1.2
-      with:
-        path: ~/.cargo/bin
-        key: cargo-installed-crates-2
-
-    - run: cargo install cargo-bisect-rustc || true
-
-    - name: Bisect
-      run: cargo bisect-rustc {} --access=github | grep -v "for x86_64-unknown-linux-gnu" || true
-        "#,
-        bisect_cmds,
-    )).await?;
-    let github_workflow = create_tree(&[TreeEntry {
-        path: "bisect.yaml".to_string(),
-        mode: TreeEntryMode::File,
-        type_: TreeEntryType::Blob,
-        sha: github_workflow_bisect,
-    }]).await?;
-    let github = create_tree(&[TreeEntry {
-        path: "workflows".to_string(),
-        mode: TreeEntryMode::Subdirectory,
-        type_: TreeEntryType::Tree,
-        sha: github_workflow,
-    }]).await?;
-
-    let cargo = create_blob(r#"[package]
-name = "cargo-bisect-bot-job"
-version = "0.0.0"
-edition = "2018"
-publish = false
-
-[dependencies]
-    "#).await?;
-
-    let root = create_tree(&[
-        TreeEntry {
-            path: "src".to_string(),
-            mode: TreeEntryMode::Subdirectory,
-            type_: TreeEntryType::Tree,
-            sha: src,
-        },
-        TreeEntry {
-            path: ".github".to_string(),
-            mode: TreeEntryMode::Subdirectory,
-            type_: TreeEntryType::Tree,
-            sha: github,
-        },
-        TreeEntry {
-            path: "Cargo.toml".to_string(),
-            mode: TreeEntryMode::File,
-            type_: TreeEntryType::Blob,
-            sha: cargo,
+/// A bot with fake runner and replies, handling comments in a single GitHub issue.
+#[cfg(test)]
+struct TestBot {
+    runner: crate::runner::FakeRunner,
+    store: crate::store::SqliteStore,
+    replies: crate::context::FakeReplies,
+    maintainers: Vec<String>,
+}
+
+#[cfg(test)]
+impl TestBot {
+    fn new() -> Self {
+        TestBot {
+            runner: Default::default(),
+            store: crate::store::SqliteStore::open(":memory:").unwrap(),
+            replies: Default::default(),
+            maintainers: vec!["github:carol".to_string()],
         }
-    ]).await?;
+    }
 
-    let commit = create_commit(
-        &format!("Bisect job for comment id {}\n\n{}", job_id, reply_to.to_commit_header()),
-        &root,
-        &[],
-    ).await?;
+    fn ctx(&self) -> Context<'_> {
+        Context {
+            runner: &self.runner,
+            store: &self.store,
+            replies: &self.replies,
+            maintainers: &self.maintainers,
+        }
+    }
 
-    push_branch(&format!("job-{}", job_id), &commit).await?;
+    fn issue() -> ReplyTo {
+        ReplyTo::Github { repo: "bjorn3/cargo-bisect-rustc-bot".to_string(), issue_number: 1 }
+    }
 
-    Ok(())
+    async fn comment(&self, login: &str, comment_id: &str, comment: &str, edited: bool) {
+        let requester = Requester::Github { login: login.to_string() };
+        parse_comment(self.ctx(), &Self::issue(), requester, comment_id, comment, edited).await.unwrap();
+    }
+
+    fn jobs(&self) -> Vec<(String, JobStatus)> {
+        self.runner.jobs.lock().unwrap().clone()
+    }
+
+    fn last_comment(&self) -> String {
+        self.replies.comments().pop().unwrap()
+    }
 }
 
-async fn create_blob(content: &str) -> reqwest::Result<String> {
-    let res = crate::github::gh_api_post(&format!("https://api.github.com/repos/{}/git/blobs", JOB_REPO), serde_json::to_string(&serde_json::json!({
-        "content": content,
-        "encoding": "utf-8",
-    })).unwrap()).await?;
-    let res: serde_json::Value = serde_json::from_str(&res).unwrap();
-    let sha = res["sha"].as_str().unwrap().to_string();
-    println!("created blob: {}", sha);
-    Ok(sha)
+#[cfg(test)]
+fn bisect_command(args: &str) -> String {
+    format!("bisect-bot bisect {}\n```rust\nfn main() {{}}\n```", args)
 }
 
-async fn create_tree(content: &[TreeEntry]) -> reqwest::Result<String> {
-    let res = crate::github::gh_api_post(&format!("https://api.github.com/repos/{}/git/trees", JOB_REPO), serde_json::to_string(&serde_json::json!({
-        "tree": content,
-    })).unwrap()).await?;
-    let res: serde_json::Value = serde_json::from_str(&res).unwrap();
-    let sha = res["sha"].as_str().unwrap().to_string();
-    println!("created tree: {}", sha);
-    Ok(sha)
+#[tokio::test]
+async fn test_duplicate_request() {
+    let bot = TestBot::new();
+    bot.comment("alice", "gh1", &bisect_command("end=2020-05-01"), false).await;
+    assert_eq!(bot.jobs(), vec![("gh1".to_string(), JobStatus::Queued)]);
+    assert_eq!(bot.replies.comments(), vec!["bisection job gh1 queued"]);
+
+    // A redelivered webhook
+    bot.comment("alice", "gh1", &bisect_command("end=2020-05-01"), false).await;
+    assert_eq!(bot.jobs().len(), 1);
+    assert_eq!(bot.replies.comments().len(), 1);
 }
 
-#[derive(serde::Serialize)]
-struct TreeEntry {
-    path: String,
-    mode: TreeEntryMode,
-    #[serde(rename = "type")]
-    type_: TreeEntryType,
-    sha: String,
+#[tokio::test]
+async fn test_edited_request() {
+    use crate::store::{JobState, JobStore};
+
+    let bot = TestBot::new();
+    bot.comment("alice", "gh1", &bisect_command("end=2020-05-01"), false).await;
+    // Edits which don't change the bisection are ignored
+    bot.comment("alice", "gh1", &format!("{}\nthanks!", bisect_command("end=2020-05-01")), true).await;
+    assert_eq!(bot.jobs().len(), 1);
+
+    bot.comment("alice", "gh1", &bisect_command("end=2020-06-01"), true).await;
+    assert_eq!(bot.jobs(), vec![("gh1".to_string(), JobStatus::Cancelled), ("gh1-2".to_string(), JobStatus::Queued)]);
+    assert!(bot.replies.comments().contains(&"The command was edited. Bisection job `gh1-2` replaces `gh1`.".to_string()));
+    assert_eq!(bot.store.get("gh1").unwrap().unwrap().state, JobState::Cancelled);
+    assert_eq!(bot.store.get("gh1-2").unwrap().unwrap().state, JobState::Queued);
 }
 
-#[derive(serde::Serialize)]
-enum TreeEntryMode {
-    #[serde(rename = "100644")]
-    File,
-    #[serde(rename = "100755")]
-    Executable,
-    #[serde(rename = "040000")]
-    Subdirectory,
-    #[serde(rename = "160000")]
-    Submodule,
-    #[serde(rename = "120000")]
-    Symlink,
+#[tokio::test]
+async fn test_retry() {
+    let bot = TestBot::new();
+    bot.comment("alice", "gh1", &bisect_command("end=2020-05-01"), false).await;
+
+    bot.comment("bob", "gh2", "bisect-bot retry", false).await;
+    assert_eq!(bot.last_comment(), "Error: only alice or a maintainer can retry bisection job `gh1`.");
+    assert_eq!(bot.jobs().len(), 1);
+
+    bot.comment("alice", "gh3", "bisect-bot retry", false).await;
+    assert_eq!(bot.jobs(), vec![("gh1".to_string(), JobStatus::Cancelled), ("gh1-2".to_string(), JobStatus::Queued)]);
+    assert!(bot.replies.comments().contains(&"Retrying bisection job `gh1` as `gh1-2`.".to_string()));
 }
 
-#[derive(serde::Serialize)]
-enum TreeEntryType {
-    #[serde(rename = "blob")]
-    Blob,
-    #[serde(rename = "tree")]
-    Tree,
-    #[serde(rename = "commit")]
-    Commit,
+#[tokio::test]
+async fn test_cancel_permissions() {
+    use crate::store::{JobState, JobStore};
+
+    let bot = TestBot::new();
+    bot.comment("alice", "gh1", &bisect_command("end=2020-05-01"), false).await;
+
+    bot.comment("bob", "gh2", "bisect-bot cancel gh1", false).await;
+    assert_eq!(bot.last_comment(), "Error: only alice or a maintainer can cancel bisection job `gh1`.");
+    assert_eq!(bot.jobs(), vec![("gh1".to_string(), JobStatus::Queued)]);
+
+    bot.comment("Carol", "gh3", "bisect-bot cancel gh1", false).await;
+    assert_eq!(bot.last_comment(), "Cancelled bisection job `gh1`.");
+    assert_eq!(bot.jobs(), vec![("gh1".to_string(), JobStatus::Cancelled)]);
+    assert_eq!(bot.store.get("gh1").unwrap().unwrap().state, JobState::Cancelled);
+
+    bot.comment("alice", "gh4", "bisect-bot cancel gh1", false).await;
+    assert_eq!(bot.last_comment(), "Error: bisection job `gh1` already finished.");
 }
 
-async fn create_commit(message: &str, tree: &str, parents: &[&str]) -> reqwest::Result<String> {
-    let res = crate::github::gh_api_post(&format!("https://api.github.com/repos/{}/git/commits", JOB_REPO), serde_json::to_string(&serde_json::json!({
-        "message": message,
-        "tree": tree,
-        "parents": parents,
-    })).unwrap()).await?;
-    let res: serde_json::Value = serde_json::from_str(&res).unwrap();
-    let sha = res["sha"].as_str().unwrap().to_string();
-    println!("created commit: {}", sha);
-    Ok(sha)
+#[tokio::test]
+async fn test_notify_check() {
+    use crate::context::Reply;
+
+    let bot = TestBot::new();
+    bot.comment("alice", "gh1", &bisect_command("end=2020-05-01 notify=rust-lang/rust#1"), false).await;
+    assert!(bot.last_comment().starts_with("Error: only maintainers can notify issues outside of"));
+    assert!(bot.jobs().is_empty());
+
+    bot.comment("carol", "gh2", &bisect_command("end=2020-05-01 notify=rust-lang/rust#1"), false).await;
+    assert_eq!(bot.jobs(), vec![("gh2".to_string(), JobStatus::Queued)]);
+    let other_issue = ReplyTo::Github { repo: "rust-lang/rust".to_string(), issue_number: 1 };
+    let sent = bot.replies.sent.lock().unwrap();
+    assert!(sent.contains(&Reply::Comment(TestBot::issue(), "bisection job gh2 queued".to_string())));
+    assert!(sent.contains(&Reply::Comment(other_issue, "bisection job gh2 queued".to_string())));
 }
 
-async fn push_branch(branch: &str, commit: &str) -> reqwest::Result<()> {
-    let res = crate::github::gh_api_post(&format!("https://api.github.com/repos/{}/git/refs", JOB_REPO), serde_json::to_string(&serde_json::json!({
-        "ref": format!("refs/heads/{}", branch),
-        "sha": commit,
-    })).unwrap()).await?;
-    println!("pushed branch: {}", res);
-    Ok(())
+#[tokio::test]
+async fn test_notify_unreachable() {
+    let mut bot = TestBot::new();
+    bot.replies.unreachable.push(ReplyTo::Github { repo: "rust-lang/rust".to_string(), issue_number: 1 });
+    bot.comment("carol", "gh1", &bisect_command("end=2020-05-01 notify=rust-lang/rust#1"), false).await;
+    assert_eq!(bot.last_comment(), "Error: the bot can't post to rust-lang/rust#1, it isn't installed there.");
+    assert!(bot.jobs().is_empty());
 }
@@ -0,0 +1,218 @@
+/// The outcome of a bisection as reported by cargo-bisect-rustc in the job log.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct BisectReport {
+    pub(crate) searched_nightlies: Option<(String, String)>,
+    pub(crate) regressed_nightly: Option<String>,
+    pub(crate) searched_commits: Option<(String, String)>,
+    /// Sha of the rust-lang/rust commit that introduced the regression.
+    pub(crate) regressed_commit: Option<String>,
+    /// Why the bisection failed, if it did.
+    pub(crate) error: Option<String>,
+}
+
+const RUST_COMMIT_URL: &str = "https://github.com/rust-lang/rust/commit/";
+
+/// Strip the timestamp GitHub Actions puts in front of every log line.
+fn strip_timestamp(line: &str) -> &str {
+    match line.split_once(' ') {
+        Some((timestamp, rest)) if chrono::DateTime::parse_from_rfc3339(timestamp).is_ok() => rest,
+        _ => line,
+    }
+}
+
+fn commit_sha(commit: &str) -> String {
+    commit.trim().trim_start_matches(RUST_COMMIT_URL).to_string()
+}
+
+fn parse_range(range: &str) -> Option<(String, String)> {
+    let (from, to) = range.trim().strip_prefix("from ")?.split_once(" to ")?;
+    Some((from.trim().to_string(), to.trim().to_string()))
+}
+
+impl BisectReport {
+    pub(crate) fn parse(log: &str) -> Self {
+        let mut report = BisectReport::default();
+        for line in log.lines() {
+            let line = strip_timestamp(line.trim_end()).trim();
+            if let Some(range) = line.strip_prefix("searched nightlies:") {
+                report.searched_nightlies = parse_range(range);
+            } else if let Some(nightly) = line.strip_prefix("regressed nightly:") {
+                report.regressed_nightly = Some(nightly.trim().to_string());
+            } else if let Some(range) = line.strip_prefix("searched commits:") {
+                report.searched_commits = parse_range(range)
+                    .map(|(from, to)| (commit_sha(&from), commit_sha(&to)));
+            } else if let Some(commit) = line.strip_prefix("regressed commit:") {
+                report.regressed_commit = Some(commit_sha(commit));
+            } else if let Some(error) = line.strip_prefix("ERROR:").or_else(|| line.strip_prefix("Error:")) {
+                report.error = Some(error.trim().to_string());
+            }
+        }
+        report
+    }
+
+    /// Whether the log contained anything we recognised.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == BisectReport::default()
+    }
+
+    /// Convert to the result format reported by the bisect workflow.
+    pub(crate) fn to_result(&self) -> BisectResult {
+        BisectResult {
+            schema_version: 1,
+            status: if self.error.is_some() {
+                BisectStatus::Error
+            } else if self.regressed_nightly.is_some() || self.regressed_commit.is_some() {
+                BisectStatus::Found
+            } else {
+                BisectStatus::NotFound
+            },
+            exit_code: if self.error.is_some() { 1 } else { 0 },
+            searched_nightlies: self.searched_nightlies.clone(),
+            regressed_toolchain: self.regressed_nightly.clone(),
+            searched_commits: self.searched_commits.clone(),
+            commit: self.regressed_commit.clone(),
+            stderr_tail: self.error.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// How a bisection job ended, as reported by the bisect workflow.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BisectStatus {
+    /// cargo-bisect-rustc found the regressing toolchain.
+    Found,
+    /// cargo-bisect-rustc succeeded, but didn't report a regression.
+    NotFound,
+    /// cargo-bisect-rustc exited with an error.
+    Error,
+}
+
+/// The `bisect-result.json` written by the bisect workflow.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct BisectResult {
+    pub(crate) schema_version: u32,
+    pub(crate) status: BisectStatus,
+    pub(crate) exit_code: i32,
+    /// The first and last nightly of the searched range.
+    #[serde(default)]
+    pub(crate) searched_nightlies: Option<(String, String)>,
+    pub(crate) regressed_toolchain: Option<String>,
+    /// Shas of the first and last rust-lang/rust commit of the searched range.
+    #[serde(default)]
+    pub(crate) searched_commits: Option<(String, String)>,
+    /// Sha of the rust-lang/rust commit that introduced the regression.
+    pub(crate) commit: Option<String>,
+    /// The last lines cargo-bisect-rustc wrote to stderr.
+    pub(crate) stderr_tail: String,
+}
+
+/// Whether `sha` is a full git commit sha.
+fn is_commit_sha(sha: &str) -> bool {
+    sha.len() == 40 && sha.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl BisectResult {
+    /// The commit that introduced the regression. The result is reported by the job, which the repro
+    /// or script can influence, so anything that isn't a commit sha is ignored.
+    pub(crate) fn regressed_commit(&self) -> Option<&str> {
+        self.commit.as_deref().filter(|sha| is_commit_sha(sha))
+    }
+
+    /// Render the result as markdown. `pr` is the rust-lang/rust PR that merged the regressed
+    /// commit, if known.
+    pub(crate) fn to_markdown(&self, pr: Option<u64>) -> String {
+        let mut summary = String::new();
+        match self.status {
+            BisectStatus::Found => {}
+            BisectStatus::NotFound => summary.push_str("No regression found.\n"),
+            BisectStatus::Error => summary.push_str(&format!("cargo-bisect-rustc exited with code {}.\n", self.exit_code)),
+        }
+        if let Some((from, to)) = &self.searched_nightlies {
+            summary.push_str(&format!("searched nightlies: from {} to {}\n", from, to));
+        }
+        if let Some(toolchain) = &self.regressed_toolchain {
+            summary.push_str(&format!("regressed nightly: {}\n", toolchain));
+        }
+        if let Some((from, to)) = self.searched_commits.as_ref().filter(|(from, to)| is_commit_sha(from) && is_commit_sha(to)) {
+            summary.push_str(&format!("searched commits: from rust-lang/rust@{} to rust-lang/rust@{}\n", from, to));
+        }
+        if let Some(commit) = self.regressed_commit() {
+            summary.push_str(&format!("regressed commit: rust-lang/rust@{}\n", commit));
+            if let Some(pr) = pr {
+                summary.push_str(&format!("regressed in: https://github.com/rust-lang/rust/pull/{}\n", pr));
+            }
+        }
+        if self.status == BisectStatus::Error && !self.stderr_tail.trim().is_empty() {
+            summary.push_str(&format!("\n```text\n{}\n```\n", self.stderr_tail.trim_end()));
+        }
+        summary
+    }
+}
+
+/// Extract the PR number from a bors merge commit message like `Auto merge of #12345 - ...`.
+pub(crate) fn merged_pr(commit_message: &str) -> Option<u64> {
+    let rest = commit_message.lines().next()?.strip_prefix("Auto merge of #")?;
+    rest[..rest.find(|c: char| !c.is_ascii_digit())?].parse().ok()
+}
+
+#[test]
+fn test_parse_report() {
+    let log = "\
+2020-05-03T10:00:00.0000000Z ##[group]Run cargo bisect-rustc \"${args[@]}\" --access=github
+2020-05-03T10:00:01.0000000Z installing nightly-2020-04-01
+2020-05-03T10:20:00.0000000Z searched nightlies: from nightly-2020-04-01 to nightly-2020-05-01
+2020-05-03T10:20:00.0000000Z regressed nightly: nightly-2020-04-19
+2020-05-03T10:20:00.0000000Z searched commits: from https://github.com/rust-lang/rust/commit/7f3df5772439eee1c512ed2eb540beef1124d236 to https://github.com/rust-lang/rust/commit/52fa23add6fb0776b32cc591ac928618391bdf41
+2020-05-03T10:20:00.0000000Z regressed commit: https://github.com/rust-lang/rust/commit/339f574809bf8e4166b8de3cdbe7df181d37af3d
+";
+    let report = BisectReport::parse(log);
+    assert_eq!(
+        report,
+        BisectReport {
+            searched_nightlies: Some(("nightly-2020-04-01".to_string(), "nightly-2020-05-01".to_string())),
+            regressed_nightly: Some("nightly-2020-04-19".to_string()),
+            searched_commits: Some((
+                "7f3df5772439eee1c512ed2eb540beef1124d236".to_string(),
+                "52fa23add6fb0776b32cc591ac928618391bdf41".to_string(),
+            )),
+            regressed_commit: Some("339f574809bf8e4166b8de3cdbe7df181d37af3d".to_string()),
+            error: None,
+        },
+    );
+    assert_eq!(
+        report.to_result().to_markdown(Some(71296)),
+        "searched nightlies: from nightly-2020-04-01 to nightly-2020-05-01\n\
+        regressed nightly: nightly-2020-04-19\n\
+        searched commits: from rust-lang/rust@7f3df5772439eee1c512ed2eb540beef1124d236 to rust-lang/rust@52fa23add6fb0776b32cc591ac928618391bdf41\n\
+        regressed commit: rust-lang/rust@339f574809bf8e4166b8de3cdbe7df181d37af3d\n\
+        regressed in: https://github.com/rust-lang/rust/pull/71296\n",
+    );
+
+    let report = BisectReport::parse(
+        "2020-05-03T10:00:01.0000000Z ERROR: the start of the range (nightly-2020-04-01) must not reproduce the regression\n",
+    );
+    assert_eq!(report.error.as_deref(), Some("the start of the range (nightly-2020-04-01) must not reproduce the regression"));
+    assert_eq!(report.to_result().status, BisectStatus::Error);
+    assert!(!report.is_empty());
+    assert!(BisectReport::parse("compiling foo\n").is_empty());
+}
+
+#[test]
+fn test_result_commit_is_checked() {
+    let mut result = BisectReport::parse(
+        "searched commits: from 7f3df5772439eee1c512ed2eb540beef1124d236 to ../../pulls\n\
+        regressed commit: 339f574809bf8e4166b8de3cdbe7df181d37af3d\n",
+    ).to_result();
+    assert_eq!(result.regressed_commit(), Some("339f574809bf8e4166b8de3cdbe7df181d37af3d"));
+    assert_eq!(result.to_markdown(None), "regressed commit: rust-lang/rust@339f574809bf8e4166b8de3cdbe7df181d37af3d\n");
+    result.commit = Some("339f574809bf8e4166b8de3cdbe7df181d37af3d/../../../orgs".to_string());
+    assert_eq!(result.regressed_commit(), None);
+    assert_eq!(result.to_markdown(None), "");
+}
+
+#[test]
+fn test_merged_pr() {
+    assert_eq!(merged_pr("Auto merge of #71296 - Mark-Simulacrum:foo, r=pietroalbini\n\nFoo"), Some(71296));
+    assert_eq!(merged_pr("Rollup merge of #123 - a:b"), None);
+}
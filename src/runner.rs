@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use hyper::{Body, Request, Response};
+
+use crate::job::Job;
+use crate::report::{BisectResult, BisectStatus};
+use crate::ReplyTo;
+
+pub(crate) mod github_actions;
+pub(crate) mod gitlab;
+pub(crate) mod local;
+pub(crate) mod worker;
+
+/// Where a job is in its lifecycle.
+#[allow(clippy::large_enum_variant)] // Statuses are short-lived
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JobStatus {
+    Queued,
+    Running {
+        url: Option<String>,
+    },
+    Completed {
+        /// `None` if the runner couldn't find out what cargo-bisect-rustc did.
+        result: Option<BisectResult>,
+        /// Markdown description of the result.
+        summary: String,
+        url: Option<String>,
+    },
+    Cancelled,
+}
+
+/// A way to run bisection jobs.
+///
+/// Runners report progress of the jobs they run by calling [`job_updated`]. Cancellations
+/// requested through [`cancel`](Self::cancel) are reported by the caller instead.
+#[async_trait]
+pub(crate) trait JobRunner: Send + Sync {
+    /// Start running a job. Fails with [`Rejected`] for jobs the runner refuses to run.
+    async fn submit(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Stop a job if it is still queued or running.
+    async fn cancel(&self, job_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Ask the runner where a job is in its lifecycle.
+    async fn status(&self, job_id: &str) -> Result<JobStatus, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Requests to paths starting with this prefix are passed to [`handle_request`](Self::handle_request).
+    fn http_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Handle an HTTP request for the runner, like a webhook or an API call of a worker.
+    async fn handle_request(&self, _req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+        Err("runner doesn't handle HTTP requests".into())
+    }
+}
+
+/// A job the runner refuses to run. The message is shown to the requester.
+#[derive(Debug)]
+pub(crate) struct Rejected(pub(crate) &'static str);
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Refuse jobs with a script unless the runner is allowed to run scripts of anyone or the job was
+/// requested by a maintainer. Runners on our own machines run the script without a sandbox.
+pub(crate) fn check_script(job: &Job, allow_scripts: bool) -> Result<(), Rejected> {
+    if job.script.is_none() || allow_scripts || crate::MAINTAINERS.contains(&job.spec.requester.key()) {
+        Ok(())
+    } else {
+        Err(Rejected("bisection scripts are only supported for maintainers of the bot."))
+    }
+}
+
+/// A runner which only records what it was asked to do.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct FakeRunner {
+    pub(crate) jobs: std::sync::Mutex<Vec<(String, JobStatus)>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl JobRunner for FakeRunner {
+    async fn submit(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.jobs.lock().unwrap().push((job.spec.job_id.clone(), JobStatus::Queued));
+        Ok(())
+    }
+
+    async fn cancel(&self, job_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.iter_mut().find(|(id, _)| id == job_id).ok_or("unknown job")?;
+        job.1 = JobStatus::Cancelled;
+        Ok(())
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, Box<dyn std::error::Error + Send + Sync>> {
+        let jobs = self.jobs.lock().unwrap();
+        Ok(jobs.iter().find(|(id, _)| id == job_id).ok_or("unknown job")?.1.clone())
+    }
+}
+
+/// Called with `(job_id, reply_to, status)` whenever a job changes status.
+pub(crate) type StatusCallback = Arc<dyn Fn(String, ReplyTo, JobStatus) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// A [`StatusCallback`] which reports to the users using [`job_updated`].
+pub(crate) fn notify_users() -> StatusCallback {
+    Arc::new(|job_id, reply_to, status| Box::pin(async move {
+        if let Err(err) = job_updated(crate::context::global(), &job_id, &reply_to, status).await {
+            println!("failed to report status of job {}: {}", job_id, err);
+        }
+    }))
+}
+
+/// A [`StatusCallback`] for runners whose jobs are reported elsewhere, like the executor of a
+/// worker.
+pub(crate) fn ignore_updates() -> StatusCallback {
+    Arc::new(|_job_id, _reply_to, _status| Box::pin(async {}))
+}
+
+/// Pick the runner based on the `JOB_RUNNER` env var.
+pub(crate) fn from_env() -> Box<dyn JobRunner> {
+    match &*std::env::var("JOB_RUNNER").unwrap_or_else(|_| "github-actions".to_string()) {
+        "github-actions" => Box::new(github_actions::GithubActionsRunner),
+        "local" => {
+            let runner = local::LocalRunner::from_env(notify_users());
+            runner.requeue_active(&**crate::JOB_STORE);
+            Box::new(runner)
+        }
+        "worker" => Box::new(worker::WorkerRunner::from_env()),
+        "gitlab" => Box::new(gitlab::GitlabRunner::from_env()),
+        runner => panic!("unknown job runner {:?}", runner),
+    }
+}
+
+/// A plain text response to an HTTP request for a runner.
+pub(crate) fn response(status: hyper::StatusCode, body: impl Into<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(Response::builder().status(status).body(body.into())?)
+}
+
+/// Check a secret sent by a client. This compares in constant time to not leak the secret through
+/// timing.
+pub(crate) fn token_matches(expected: &str, actual: &[u8]) -> bool {
+    expected.len() == actual.len()
+        && expected.bytes().zip(actual).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// The branch a job is pushed to by runners using a git repository.
+pub(crate) fn branch_name(job_id: &str) -> String {
+    format!("job-{}", job_id)
+}
+
+/// The inverse of [`branch_name`].
+pub(crate) fn job_id_from_branch(branch: &str) -> Option<&str> {
+    branch.strip_prefix("job-")
+}
+
+/// The contents of the status message of a job which reached `status`.
+fn status_message(job_id: &str, status: &JobStatus) -> String {
+    match status {
+        JobStatus::Queued => format!("bisection job {} queued", job_id),
+        JobStatus::Running { url: Some(url) } => format!("bisection job {} started: {}", job_id, url),
+        JobStatus::Running { url: None } => format!("bisection job {} started", job_id),
+        JobStatus::Completed { result, summary, url } => {
+            let failed = result.as_ref().is_some_and(|result| result.status == BisectStatus::Error);
+            let mut message = format!("bisection job {} {}", job_id, if failed { "failed" } else { "completed" });
+            if let Some(url) = url {
+                message.push_str(&format!(": {}", url));
+            }
+            if !summary.is_empty() {
+                message.push_str(&format!("\n\n{}", summary));
+            }
+            message
+        }
+        JobStatus::Cancelled => format!("bisection job {} was cancelled", job_id),
+    }
+}
+
+/// Called by the runners whenever the status of a job changes.
+pub(crate) async fn job_updated(ctx: crate::context::Context<'_>, job_id: &str, reply_to: &ReplyTo, status: JobStatus) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!("job {} is now {:?}", job_id, status);
+    let job = match ctx.store.get(job_id) {
+        Ok(job) => job,
+        Err(err) => {
+            println!("failed to load job {}: {}", job_id, err);
+            None
+        }
+    };
+    // Finished jobs stay finished. This ignores the runner reporting a cancellation after it was
+    // already reported when it was requested.
+    if job.as_ref().is_some_and(|job| !job.state.is_active()) {
+        println!("ignoring status of finished job {}", job_id);
+        return Ok(());
+    }
+    // The runner may report the job as started before it was first reported as queued. Later
+    // reports of a queued job are requeues.
+    if let (Some(job), JobStatus::Queued) = (&job, &status) {
+        let was_queued = job.transitions.iter().any(|(_, state)| *state == crate::store::JobState::Queued);
+        if !was_queued && !matches!(job.state, crate::store::JobState::Created | crate::store::JobState::Queued) {
+            println!("ignoring outdated status of job {}", job_id);
+            return Ok(());
+        }
+    }
+    let result = match &status {
+        JobStatus::Completed { result, .. } => result.as_ref(),
+        _ => None,
+    };
+    if let Err(err) = ctx.store.transition(job_id, crate::store::JobState::from_status(&status), result) {
+        println!("failed to record job {}: {}", job_id, err);
+    }
+    // Jobs the store doesn't know about only report to the thread named in their commit
+    let targets = match job {
+        Some(job) => job.targets,
+        None => vec![crate::store::JobTarget { reply_to: reply_to.clone(), status_message: None }],
+    };
+    crate::dispatcher::dispatch(ctx, job_id, &targets, &status_message(job_id, &status)).await
+}
+
+/// Render a result as markdown, linking the PR that merged the regressed commit.
+pub(crate) async fn render_result(result: &BisectResult) -> String {
+    let pr = match result.regressed_commit() {
+        Some(sha) => crate::github::merged_pr(sha).await,
+        None => None,
+    };
+    result.to_markdown(pr)
+}
+
+/// The status updates reported to a [`recording_callback`].
+#[cfg(test)]
+pub(crate) type Updates = Arc<std::sync::Mutex<Vec<(String, JobStatus)>>>;
+
+/// A [`StatusCallback`] which only records the updates.
+#[cfg(test)]
+pub(crate) fn recording_callback() -> (StatusCallback, Updates) {
+    let updates = Updates::default();
+    let callback: StatusCallback = {
+        let updates = updates.clone();
+        Arc::new(move |job_id, _reply_to, status| {
+            updates.lock().unwrap().push((job_id, status));
+            Box::pin(async {})
+        })
+    };
+    (callback, updates)
+}
+
+#[test]
+fn test_status_message() {
+    assert_eq!(status_message("gh1", &JobStatus::Queued), "bisection job gh1 queued");
+    assert_eq!(
+        status_message("gh1", &JobStatus::Running { url: Some("https://example.com/1".to_string()) }),
+        "bisection job gh1 started: https://example.com/1",
+    );
+    let result = BisectResult {
+        schema_version: 1,
+        status: BisectStatus::Error,
+        exit_code: 1,
+        searched_nightlies: None,
+        regressed_toolchain: None,
+        searched_commits: None,
+        commit: None,
+        stderr_tail: "ERROR: the start of the range must not reproduce the regression".to_string(),
+    };
+    assert_eq!(
+        status_message("gh1", &JobStatus::Completed {
+            summary: result.to_markdown(None),
+            result: Some(result),
+            url: None,
+        }),
+        "bisection job gh1 failed\n\ncargo-bisect-rustc exited with code 1.\n\n\
+        ```text\nERROR: the start of the range must not reproduce the regression\n```\n",
+    );
+}
@@ -0,0 +1,506 @@
+use async_trait::async_trait;
+
+use crate::github::{gh_api, gh_api_delete, gh_api_post};
+use crate::job::Job;
+use crate::report::{BisectReport, BisectResult};
+use crate::runner::{branch_name, JobRunner, JobStatus};
+use crate::{ReplyTo, JOB_REPO};
+
+/// Runs jobs on GitHub Actions by pushing a branch with the repro and a bisect workflow to
+/// [`JOB_REPO`]. Progress is reported back through `check_run` webhook events. Cancelling a job
+/// cancels its workflow runs and deletes its branch.
+pub(crate) struct GithubActionsRunner;
+
+#[async_trait]
+impl JobRunner for GithubActionsRunner {
+    async fn submit(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        push_job(job).await
+    }
+
+    async fn cancel(&self, job_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        #[derive(serde::Deserialize)]
+        struct WorkflowRun {
+            id: u64,
+            status: String,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct WorkflowRuns {
+            workflow_runs: Vec<WorkflowRun>,
+        }
+
+        let res = gh_api(JOB_REPO, &format!(
+            "https://api.github.com/repos/{}/actions/runs?branch={}",
+            JOB_REPO, branch_name(job_id),
+        )).await?;
+        let runs: WorkflowRuns = serde_json::from_str(&res)?;
+        for run in runs.workflow_runs.iter().filter(|run| run.status != "completed") {
+            gh_api_post(JOB_REPO, &format!(
+                "https://api.github.com/repos/{}/actions/runs/{}/cancel",
+                JOB_REPO, run.id,
+            ), String::new()).await?;
+        }
+        gh_api_delete(JOB_REPO, &format!(
+            "https://api.github.com/repos/{}/git/refs/heads/{}",
+            JOB_REPO, branch_name(job_id),
+        )).await
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, Box<dyn std::error::Error + Send + Sync>> {
+        #[derive(serde::Deserialize)]
+        struct CheckRuns {
+            check_runs: Vec<CheckRun>,
+        }
+
+        let res = gh_api(JOB_REPO, &format!(
+            "https://api.github.com/repos/{}/commits/{}/check-runs?check_name={}",
+            JOB_REPO, branch_name(job_id), BISECT_CHECK_RUN,
+        )).await?;
+        let check_runs: CheckRuns = serde_json::from_str(&res)?;
+        match check_runs.check_runs.first() {
+            Some(check_run) => Ok(check_run_status(check_run).await),
+            None => Ok(JobStatus::Queued),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Commit {
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CheckSuite {
+    head_branch: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct App {
+    slug: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CheckRun {
+    id: u64,
+    name: String,
+    head_sha: String,
+    html_url: String,
+    status: String,
+    conclusion: Option<String>,
+    check_suite: CheckSuite,
+    app: App,
+}
+
+#[derive(serde::Deserialize)]
+struct CheckRunEvent {
+    action: String,
+    check_run: CheckRun,
+    repository: crate::github::Repository,
+}
+
+/// The app whose check runs report the progress of jobs.
+const GITHUB_ACTIONS_APP: &str = "github-actions";
+
+/// The name of the check run for the job of [`BISECT_WORKFLOW`].
+const BISECT_CHECK_RUN: &str = "bisect";
+
+/// The job a `check_run` event is about, or why the event isn't about a job.
+fn check_run_job_id(event: &CheckRunEvent) -> Result<&str, &'static str> {
+    if event.repository.full_name != JOB_REPO {
+        return Err("check run is not in the job repo");
+    }
+    if event.check_run.app.slug != GITHUB_ACTIONS_APP {
+        return Err("check run is not from GitHub Actions");
+    }
+    if event.check_run.name != BISECT_CHECK_RUN {
+        return Err("check run is not for the bisect workflow");
+    }
+    event.check_run.check_suite.head_branch.as_deref()
+        .and_then(crate::runner::job_id_from_branch)
+        .ok_or("check run is not for a job branch")
+}
+
+/// Handle a `check_run` webhook event for a job. Returns the response to the webhook, which says
+/// why the event was ignored if it was.
+pub(crate) async fn handle_check_run(event: serde_json::Value) -> Result<&'static str, Box<dyn std::error::Error + Send + Sync>> {
+    let event: CheckRunEvent = serde_json::from_value(event)?;
+    println!("check_run action: {}", event.action);
+    let job_id = match check_run_job_id(&event) {
+        Ok(job_id) => job_id,
+        Err(reason) => {
+            println!("ignoring check run {}: {}", event.check_run.id, reason);
+            return Ok(reason);
+        }
+    };
+    if !matches!(&*event.action, "created" | "completed") {
+        println!("ignoring check_run action {}", event.action);
+        return Ok("ignored check_run action");
+    }
+    let reply_to = {
+        let res = gh_api(JOB_REPO, &format!(
+            "https://api.github.com/repos/{}/git/commits/{}",
+            JOB_REPO, event.check_run.head_sha,
+        )).await?;
+        let commit: Commit = serde_json::from_str(&res)?;
+        // Never reply when the header is forged
+        ReplyTo::from_commit_message(&commit.message, job_id, *crate::ALLOW_UNSIGNED_REPLY_TO)
+            .map_err(|err| format!("Failed to parse commit message {:?}: {}", commit.message, err))?
+    };
+    println!("reply to: {:?}", reply_to);
+    if let Err(err) = crate::JOB_STORE.set_check_run(job_id, event.check_run.id) {
+        println!("failed to record job {}: {}", job_id, err);
+    }
+    crate::runner::job_updated(crate::context::global(), job_id, &reply_to, check_run_status(&event.check_run).await).await?;
+    Ok("processed")
+}
+
+async fn check_run_status(check_run: &CheckRun) -> JobStatus {
+    match &*check_run.status {
+        "queued" | "in_progress" => JobStatus::Running { url: Some(check_run.html_url.clone()) },
+        _ if check_run.conclusion.as_deref() == Some("cancelled") => JobStatus::Cancelled,
+        _ => {
+            let (result, summary) = completion_summary(check_run).await;
+            JobStatus::Completed { result, summary, url: Some(check_run.html_url.clone()) }
+        }
+    }
+}
+
+/// Summarize the result of a finished bisection job. This uses the result the workflow reports in
+/// a check run annotation and falls back to scraping the log for jobs that don't report one.
+async fn completion_summary(check_run: &CheckRun) -> (Option<BisectResult>, String) {
+    match job_result(check_run.id).await {
+        Ok(Some(result)) => {
+            let summary = crate::runner::render_result(&result).await;
+            return (Some(result), summary);
+        }
+        Ok(None) => println!("no result annotation for job {}", check_run.id),
+        Err(err) => println!("failed to get result of job {}: {}", check_run.id, err),
+    }
+
+    let log = match gh_api(JOB_REPO, &format!(
+        "https://api.github.com/repos/{}/actions/jobs/{}/logs",
+        JOB_REPO, check_run.id,
+    )).await {
+        Ok(log) => log,
+        Err(err) => {
+            println!("failed to fetch log of job {}: {}", check_run.id, err);
+            return (None, String::new());
+        }
+    };
+
+    let report = BisectReport::parse(&log);
+    if report.is_empty() {
+        return (None, "No result was found in the log.".to_string());
+    }
+
+    let result = report.to_result();
+    let summary = crate::runner::render_result(&result).await;
+    (Some(result), summary)
+}
+
+async fn job_result(check_run_id: u64) -> Result<Option<BisectResult>, Box<dyn std::error::Error + Send + Sync>> {
+    let res = gh_api(JOB_REPO, &format!(
+        "https://api.github.com/repos/{}/check-runs/{}/annotations",
+        JOB_REPO, check_run_id,
+    )).await?;
+    parse_result_annotation(&res)
+}
+
+/// Title of the annotation the bisect workflow reports its result in.
+const RESULT_ANNOTATION: &str = "bisect-result";
+
+/// Find the result of the bisect workflow in the annotations of its check run. The message of the
+/// annotation is the base64 encoded `bisect-result.json` written by the workflow.
+fn parse_result_annotation(annotations: &str) -> Result<Option<BisectResult>, Box<dyn std::error::Error + Send + Sync>> {
+    #[derive(serde::Deserialize)]
+    struct Annotation {
+        title: Option<String>,
+        message: String,
+    }
+
+    let annotations: Vec<Annotation> = serde_json::from_str(annotations)?;
+    let annotation = match annotations.iter().rev().find(|annotation| annotation.title.as_deref() == Some(RESULT_ANNOTATION)) {
+        Some(annotation) => annotation,
+        None => return Ok(None),
+    };
+    let result = base64::decode(annotation.message.trim())?;
+    Ok(Some(serde_json::from_slice(&result)?))
+}
+
+#[test]
+fn test_parse_result_annotation() {
+    use crate::report::BisectStatus;
+
+    let result = r#"{"schema_version":1,"status":"found","exit_code":0,"regressed_toolchain":"nightly-2020-04-19","commit":"339f574809bf8e4166b8de3cdbe7df181d37af3d","stderr_tail":"checking the start range to verify it passes\n"}"#;
+    let annotations = serde_json::json!([
+        {
+            "path": ".github",
+            "annotation_level": "warning",
+            "title": null,
+            "message": "Unrelated warning",
+        },
+        {
+            "path": ".github",
+            "annotation_level": "notice",
+            "title": "bisect-result",
+            "message": base64::encode(result),
+        },
+    ]).to_string();
+    assert_eq!(
+        parse_result_annotation(&annotations).unwrap(),
+        Some(BisectResult {
+            schema_version: 1,
+            status: BisectStatus::Found,
+            exit_code: 0,
+            searched_nightlies: None,
+            regressed_toolchain: Some("nightly-2020-04-19".to_string()),
+            searched_commits: None,
+            commit: Some("339f574809bf8e4166b8de3cdbe7df181d37af3d".to_string()),
+            stderr_tail: "checking the start range to verify it passes\n".to_string(),
+        }),
+    );
+
+    assert_eq!(parse_result_annotation("[]").unwrap(), None);
+    let garbage = serde_json::json!([{ "title": "bisect-result", "message": "not base64!" }]).to_string();
+    assert!(parse_result_annotation(&garbage).is_err());
+}
+
+#[test]
+fn test_check_run_job_id() {
+    let event = |repo: &str, app: &str, name: &str, branch: &str| -> CheckRunEvent {
+        serde_json::from_value(serde_json::json!({
+            "action": "completed",
+            "check_run": {
+                "id": 1,
+                "name": name,
+                "head_sha": "abc",
+                "html_url": "https://github.com/bjorn3/cargo-bisect-rustc-bot-jobs/runs/1",
+                "status": "completed",
+                "conclusion": "success",
+                "check_suite": { "head_branch": branch },
+                "app": { "slug": app },
+            },
+            "repository": { "full_name": repo },
+        })).unwrap()
+    };
+    assert_eq!(check_run_job_id(&event(JOB_REPO, "github-actions", "bisect", "job-gh1")), Ok("gh1"));
+    assert_eq!(
+        check_run_job_id(&event("bjorn3/cargo-bisect-rustc-bot", "github-actions", "bisect", "job-gh1")),
+        Err("check run is not in the job repo"),
+    );
+    assert_eq!(
+        check_run_job_id(&event(JOB_REPO, "travis-ci", "bisect", "job-gh1")),
+        Err("check run is not from GitHub Actions"),
+    );
+    assert_eq!(
+        check_run_job_id(&event(JOB_REPO, "github-actions", "build", "job-gh1")),
+        Err("check run is not for the bisect workflow"),
+    );
+    assert_eq!(
+        check_run_job_id(&event(JOB_REPO, "github-actions", "bisect", "master")),
+        Err("check run is not for a job branch"),
+    );
+}
+
+/// The workflow running the bisection using [`RUN_SCRIPT`](crate::job::RUN_SCRIPT). This must not
+/// contain any user input. All job parameters are read from files in the job tree instead.
+const BISECT_WORKFLOW: &str = r#"
+name: Bisect
+
+on:
+  - push
+
+# The repro and `--script` are untrusted, so don't give them a token which can write to the repo
+permissions:
+  contents: read
+
+jobs:
+  # The check run of this job must be named like BISECT_CHECK_RUN
+  bisect:
+    runs-on: ubuntu-latest
+
+    steps:
+    - uses: actions/checkout@v2
+      with:
+        persist-credentials: false
+
+    - name: Cache cargo installed crates
+      uses: actions/cache@v1.1.2
+      with:
+        path: ~/.cargo/bin
+        key: cargo-installed-crates-2
+
+    - run: cargo install cargo-bisect-rustc || true
+
+    - name: Bisect
+      run: |
+        # Disable workflow commands while the user script runs and its output is shown, so that it
+        # can't forge the result annotation
+        stop_token=$(head -c 32 /dev/urandom | sha256sum | cut -d' ' -f1)
+        echo "::stop-commands::$stop_token"
+        ./run-bisect.sh
+        echo "::$stop_token::"
+        # Report the result to the bot as check run annotation
+        echo "::notice title=bisect-result::$(base64 -w0 bisect-result.json)"
+"#;
+
+async fn push_job(job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let job_file = create_blob(&job.spec.to_json()).await?;
+
+    let src_lib = create_blob(&job.code).await?;
+    let src = create_tree(&[TreeEntry {
+        path: "lib.rs".to_string(),
+        mode: TreeEntryMode::File,
+        type_: TreeEntryType::Blob,
+        sha: src_lib,
+    }]).await?;
+
+    let github_workflow_bisect = create_blob(BISECT_WORKFLOW).await?;
+    let github_workflow = create_tree(&[TreeEntry {
+        path: "bisect.yaml".to_string(),
+        mode: TreeEntryMode::File,
+        type_: TreeEntryType::Blob,
+        sha: github_workflow_bisect,
+    }]).await?;
+    let github = create_tree(&[TreeEntry {
+        path: "workflows".to_string(),
+        mode: TreeEntryMode::Subdirectory,
+        type_: TreeEntryType::Tree,
+        sha: github_workflow,
+    }]).await?;
+
+    let cargo = create_blob(crate::job::CARGO_TOML).await?;
+
+    let mut root_entries = vec![
+        TreeEntry {
+            path: "src".to_string(),
+            mode: TreeEntryMode::Subdirectory,
+            type_: TreeEntryType::Tree,
+            sha: src,
+        },
+        TreeEntry {
+            path: ".github".to_string(),
+            mode: TreeEntryMode::Subdirectory,
+            type_: TreeEntryType::Tree,
+            sha: github,
+        },
+        TreeEntry {
+            path: "Cargo.toml".to_string(),
+            mode: TreeEntryMode::File,
+            type_: TreeEntryType::Blob,
+            sha: cargo,
+        },
+        TreeEntry {
+            path: crate::job::JOB_FILE.to_string(),
+            mode: TreeEntryMode::File,
+            type_: TreeEntryType::Blob,
+            sha: job_file,
+        },
+        TreeEntry {
+            path: crate::job::RUN_FILE.to_string(),
+            mode: TreeEntryMode::Executable,
+            type_: TreeEntryType::Blob,
+            sha: create_blob(crate::job::RUN_SCRIPT).await?,
+        },
+    ];
+    if let Some(script) = &job.script {
+        root_entries.push(TreeEntry {
+            path: "bisect.sh".to_string(),
+            mode: TreeEntryMode::Executable,
+            type_: TreeEntryType::Blob,
+            sha: create_blob(&crate::job::script_file(script)).await?,
+        });
+    }
+    let root = create_tree(&root_entries).await?;
+
+    let commit = create_commit(
+        &format!("Bisect job for comment id {}\n\n{}", job.spec.job_id, job.spec.reply_to.to_commit_header(&job.spec.job_id)),
+        &root,
+        &[],
+    ).await?;
+
+    let branch = branch_name(&job.spec.job_id);
+    push_branch(&branch, &commit).await?;
+    if let Err(err) = crate::JOB_STORE.set_branch(&job.spec.job_id, &branch) {
+        println!("failed to record job {}: {}", job.spec.job_id, err);
+    }
+
+    Ok(())
+}
+
+async fn create_blob(content: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let res = gh_api_post(JOB_REPO, &format!("https://api.github.com/repos/{}/git/blobs", JOB_REPO), serde_json::to_string(&serde_json::json!({
+        "content": content,
+        "encoding": "utf-8",
+    })).unwrap()).await?;
+    let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+    let sha = res["sha"].as_str().unwrap().to_string();
+    println!("created blob: {}", sha);
+    Ok(sha)
+}
+
+async fn create_tree(content: &[TreeEntry]) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let res = gh_api_post(JOB_REPO, &format!("https://api.github.com/repos/{}/git/trees", JOB_REPO), serde_json::to_string(&serde_json::json!({
+        "tree": content,
+    })).unwrap()).await?;
+    let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+    let sha = res["sha"].as_str().unwrap().to_string();
+    println!("created tree: {}", sha);
+    Ok(sha)
+}
+
+#[derive(serde::Serialize)]
+struct TreeEntry {
+    path: String,
+    mode: TreeEntryMode,
+    #[serde(rename = "type")]
+    type_: TreeEntryType,
+    sha: String,
+}
+
+#[allow(dead_code)]
+#[derive(serde::Serialize)]
+enum TreeEntryMode {
+    #[serde(rename = "100644")]
+    File,
+    #[serde(rename = "100755")]
+    Executable,
+    #[serde(rename = "040000")]
+    Subdirectory,
+    #[serde(rename = "160000")]
+    Submodule,
+    #[serde(rename = "120000")]
+    Symlink,
+}
+
+#[allow(dead_code)]
+#[derive(serde::Serialize)]
+enum TreeEntryType {
+    #[serde(rename = "blob")]
+    Blob,
+    #[serde(rename = "tree")]
+    Tree,
+    #[serde(rename = "commit")]
+    Commit,
+}
+
+async fn create_commit(message: &str, tree: &str, parents: &[&str]) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let res = gh_api_post(JOB_REPO, &format!("https://api.github.com/repos/{}/git/commits", JOB_REPO), serde_json::to_string(&serde_json::json!({
+        "message": message,
+        "tree": tree,
+        "parents": parents,
+    })).unwrap()).await?;
+    let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+    let sha = res["sha"].as_str().unwrap().to_string();
+    println!("created commit: {}", sha);
+    Ok(sha)
+}
+
+async fn push_branch(branch: &str, commit: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let res = gh_api_post(JOB_REPO, &format!("https://api.github.com/repos/{}/git/refs", JOB_REPO), serde_json::to_string(&serde_json::json!({
+        "ref": format!("refs/heads/{}", branch),
+        "sha": commit,
+    })).unwrap()).await?;
+    println!("pushed branch: {}", res);
+    Ok(())
+}
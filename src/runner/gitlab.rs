@@ -0,0 +1,402 @@
+use async_trait::async_trait;
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::job::Job;
+use crate::report::{BisectReport, BisectResult};
+use crate::runner::{branch_name, response, JobRunner, JobStatus, StatusCallback};
+use crate::ReplyTo;
+
+/// Runs jobs on GitLab CI by committing the repro and a `.gitlab-ci.yml` to a branch of a GitLab
+/// project. Progress is reported back through pipeline webhook events sent to `/gitlab`.
+///
+/// Job branches are created from `start_branch`, which must not contain any of the job files.
+pub(crate) struct GitlabRunner {
+    /// Base url of the API, like `https://gitlab.com/api/v4`.
+    api_url: String,
+    /// Path of the project the jobs are pushed to, like `group/jobs`.
+    project: String,
+    token: String,
+    /// Secret configured for the webhook, sent by GitLab as `X-Gitlab-Token`.
+    webhook_token: String,
+    start_branch: String,
+    on_update: StatusCallback,
+}
+
+/// The CI config running the bisection using [`RUN_SCRIPT`](crate::job::RUN_SCRIPT). This must not
+/// contain any user input. All job parameters are read from files in the job tree instead.
+const GITLAB_CI: &str = r#"
+bisect:
+  image: rust:latest
+  script:
+    - apt-get update && apt-get install -y jq
+    - cargo install cargo-bisect-rustc
+    - ./run-bisect.sh
+  artifacts:
+    when: always
+    paths:
+      - bisect-result.json
+"#;
+
+/// Name of the CI job in [`GITLAB_CI`].
+const BISECT_JOB: &str = "bisect";
+
+#[derive(serde::Deserialize)]
+struct Pipeline {
+    id: u64,
+    #[serde(rename = "ref")]
+    ref_: String,
+    sha: String,
+    status: String,
+    web_url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CiJob {
+    id: u64,
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Commit {
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Project {
+    path_with_namespace: String,
+}
+
+#[derive(serde::Deserialize)]
+struct PipelineAttributes {
+    id: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct PipelineEvent {
+    object_attributes: PipelineAttributes,
+    project: Project,
+}
+
+impl GitlabRunner {
+    pub(crate) fn new(api_url: String, project: String, token: String, webhook_token: String, start_branch: String, on_update: StatusCallback) -> Self {
+        GitlabRunner {
+            api_url,
+            project,
+            token,
+            webhook_token,
+            start_branch,
+            on_update,
+        }
+    }
+
+    /// Configure the runner using `GITLAB_API_URL`, `GITLAB_PROJECT`, `GITLAB_TOKEN`,
+    /// `GITLAB_WEBHOOK_TOKEN` and `GITLAB_START_BRANCH`.
+    pub(crate) fn from_env() -> Self {
+        GitlabRunner::new(
+            std::env::var("GITLAB_API_URL").unwrap_or_else(|_| "https://gitlab.com/api/v4".to_string()),
+            std::env::var("GITLAB_PROJECT").expect("gitlab project not defined"),
+            std::env::var("GITLAB_TOKEN").expect("gitlab token not defined"),
+            std::env::var("GITLAB_WEBHOOK_TOKEN").expect("gitlab webhook token not defined"),
+            std::env::var("GITLAB_START_BRANCH").unwrap_or_else(|_| "main".to_string()),
+            crate::runner::notify_users(),
+        )
+    }
+
+    async fn api(&self, method: reqwest::Method, path: &str, body: Option<serde_json::Value>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let url = format!(
+            "{}/projects/{}{}",
+            self.api_url,
+            percent_encoding::utf8_percent_encode(&self.project, percent_encoding::NON_ALPHANUMERIC),
+            path,
+        );
+        let mut req = reqwest::Client::new()
+            .request(method, &url)
+            .header(hyper::http::header::USER_AGENT, crate::USER_AGENT)
+            .header("PRIVATE-TOKEN", &self.token);
+        if let Some(body) = body {
+            req = req
+                .header(hyper::http::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+        }
+        let res = req.send().await?.error_for_status()?;
+        Ok(res.text().await?)
+    }
+
+    async fn pipelines(&self, job_id: &str) -> Result<Vec<Pipeline>, Box<dyn std::error::Error + Send + Sync>> {
+        let res = self.api(reqwest::Method::GET, &format!(
+            "/pipelines?ref={}&order_by=id&sort=desc",
+            branch_name(job_id),
+        ), None).await?;
+        Ok(serde_json::from_str(&res)?)
+    }
+
+    async fn pipeline(&self, pipeline_id: u64) -> Result<Pipeline, Box<dyn std::error::Error + Send + Sync>> {
+        let res = self.api(reqwest::Method::GET, &format!("/pipelines/{}", pipeline_id), None).await?;
+        Ok(serde_json::from_str(&res)?)
+    }
+
+    /// The job status for a pipeline. Returns `None` for pipelines waiting on manual action.
+    async fn pipeline_status(&self, pipeline: &Pipeline) -> Option<JobStatus> {
+        match &*pipeline.status {
+            "created" | "waiting_for_resource" | "preparing" | "pending" => Some(JobStatus::Queued),
+            "running" => Some(JobStatus::Running { url: Some(pipeline.web_url.clone()) }),
+            "success" | "failed" => {
+                let (result, summary) = self.completion_summary(pipeline).await;
+                Some(JobStatus::Completed { result, summary, url: Some(pipeline.web_url.clone()) })
+            }
+            "canceled" | "skipped" => Some(JobStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    /// Summarize the result of a finished pipeline. This uses the `bisect-result.json` artifact
+    /// and falls back to scraping the log when there is none.
+    async fn completion_summary(&self, pipeline: &Pipeline) -> (Option<BisectResult>, String) {
+        let ci_job = match self.bisect_job(pipeline.id).await {
+            Ok(ci_job) => ci_job,
+            Err(err) => {
+                println!("failed to find bisect job of pipeline {}: {}", pipeline.id, err);
+                return (None, String::new());
+            }
+        };
+
+        match self.api(reqwest::Method::GET, &format!("/jobs/{}/artifacts/bisect-result.json", ci_job), None).await {
+            Ok(res) => match serde_json::from_str(&res) {
+                Ok(result) => {
+                    let summary = crate::runner::render_result(&result).await;
+                    return (Some(result), summary);
+                }
+                Err(err) => println!("invalid result of job {}: {}", ci_job, err),
+            },
+            Err(err) => println!("failed to get result of job {}: {}", ci_job, err),
+        }
+
+        let log = match self.api(reqwest::Method::GET, &format!("/jobs/{}/trace", ci_job), None).await {
+            Ok(log) => log,
+            Err(err) => {
+                println!("failed to fetch log of job {}: {}", ci_job, err);
+                return (None, String::new());
+            }
+        };
+        let report = BisectReport::parse(&log);
+        if report.is_empty() {
+            return (None, "No result was found in the log.".to_string());
+        }
+        let result = report.to_result();
+        let summary = crate::runner::render_result(&result).await;
+        (Some(result), summary)
+    }
+
+    async fn bisect_job(&self, pipeline_id: u64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let res = self.api(reqwest::Method::GET, &format!("/pipelines/{}/jobs", pipeline_id), None).await?;
+        let ci_jobs: Vec<CiJob> = serde_json::from_str(&res)?;
+        Ok(ci_jobs.iter().find(|ci_job| ci_job.name == BISECT_JOB).ok_or("pipeline has no bisect job")?.id)
+    }
+
+    /// Handle a pipeline webhook event for a job.
+    async fn handle_pipeline(&self, event: PipelineEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Only trust the pipeline id of the event and fetch everything else from the API
+        let pipeline = self.pipeline(event.object_attributes.id).await?;
+        println!("pipeline {} status: {}", pipeline.id, pipeline.status);
+        let job_id = crate::runner::job_id_from_branch(&pipeline.ref_).ok_or("pipeline is not for a job branch")?;
+        let reply_to = {
+            let res = self.api(reqwest::Method::GET, &format!("/repository/commits/{}", pipeline.sha), None).await?;
+            let commit: Commit = serde_json::from_str(&res)?;
+            ReplyTo::from_commit_message(&commit.message, job_id, *crate::ALLOW_UNSIGNED_REPLY_TO)
+                .map_err(|err| format!("Failed to parse commit message {:?}: {}", commit.message, err))?
+        };
+        println!("reply to: {:?}", reply_to);
+        if let Some(status) = self.pipeline_status(&pipeline).await {
+            (self.on_update)(job_id.to_string(), reply_to, status).await;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JobRunner for GitlabRunner {
+    async fn submit(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut actions = vec![serde_json::json!({
+            "action": "create",
+            "file_path": ".gitlab-ci.yml",
+            "content": GITLAB_CI,
+        })];
+        for (path, contents, executable) in job.files() {
+            actions.push(serde_json::json!({
+                "action": "create",
+                "file_path": path,
+                "content": contents,
+                "execute_filemode": executable,
+            }));
+        }
+        let branch = branch_name(&job.spec.job_id);
+        let res = self.api(reqwest::Method::POST, "/repository/commits", Some(serde_json::json!({
+            "branch": branch,
+            "start_branch": self.start_branch,
+            "force": true,
+            "commit_message": format!("Bisect job for comment id {}\n\n{}", job.spec.job_id, job.spec.reply_to.to_commit_header(&job.spec.job_id)),
+            "actions": actions,
+        }))).await?;
+        let res: serde_json::Value = serde_json::from_str(&res)?;
+        println!("created commit: {}", res["id"]);
+        if let Err(err) = crate::JOB_STORE.set_branch(&job.spec.job_id, &branch) {
+            println!("failed to record job {}: {}", job.spec.job_id, err);
+        }
+        Ok(())
+    }
+
+    async fn cancel(&self, job_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for pipeline in self.pipelines(job_id).await? {
+            if let "created" | "waiting_for_resource" | "preparing" | "pending" | "running" = &*pipeline.status {
+                self.api(reqwest::Method::POST, &format!("/pipelines/{}/cancel", pipeline.id), None).await?;
+            }
+        }
+        self.api(reqwest::Method::DELETE, &format!(
+            "/repository/branches/{}",
+            percent_encoding::utf8_percent_encode(&branch_name(job_id), percent_encoding::NON_ALPHANUMERIC),
+        ), None).await?;
+        Ok(())
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, Box<dyn std::error::Error + Send + Sync>> {
+        match self.pipelines(job_id).await?.first() {
+            Some(pipeline) => Ok(self.pipeline_status(pipeline).await.unwrap_or(JobStatus::Queued)),
+            None => Ok(JobStatus::Queued),
+        }
+    }
+
+    fn http_prefix(&self) -> Option<&'static str> {
+        Some("/gitlab")
+    }
+
+    async fn handle_request(&self, req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+        let authorized = req.headers().get("X-Gitlab-Token")
+            .is_some_and(|token| crate::runner::token_matches(&self.webhook_token, token.as_bytes()));
+        if !authorized {
+            println!("invalid gitlab webhook token");
+            return response(StatusCode::UNAUTHORIZED, "invalid webhook token");
+        }
+        let event = req.headers().get("X-Gitlab-Event").map(|event| event.to_str().map(|event| event.to_string()));
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+        match event {
+            Some(Ok(event)) if event == "Pipeline Hook" => {}
+            _ => return response(StatusCode::OK, "ignored: not a pipeline event"),
+        }
+
+        let event: PipelineEvent = serde_json::from_slice(&body)?;
+        if event.project.path_with_namespace != self.project {
+            println!("ignoring pipeline of project {}", event.project.path_with_namespace);
+            return response(StatusCode::OK, "ignored: not the job project");
+        }
+        self.handle_pipeline(event).await?;
+        response(StatusCode::OK, "ok")
+    }
+}
+
+#[tokio::test]
+async fn test_gitlab_runner() {
+    use hyper::service::{make_service_fn, service_fn};
+    use std::sync::{Arc, Mutex};
+
+    let commit_message = format!(
+        "Bisect job for comment id gh1\n\n{}",
+        ReplyTo::Github { repo: "a/b".to_string(), issue_number: 1 }.to_commit_header("gh1"),
+    );
+
+    // A mock of the parts of the GitLab API used by the runner
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let server = {
+        let requests = requests.clone();
+        let commit_message = commit_message.clone();
+        hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service_fn(move |_conn| {
+            let requests = requests.clone();
+            let commit_message = commit_message.clone();
+            async move {
+                Ok::<_, std::convert::Infallible>(service_fn(move |req: Request<Body>| {
+                    let requests = requests.clone();
+                    let commit_message = commit_message.clone();
+                    async move {
+                        let method = req.method().to_string();
+                        let path = req.uri().to_string();
+                        assert_eq!(req.headers()["PRIVATE-TOKEN"], "api-token");
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        requests.lock().unwrap().push((method.clone(), path.clone(), String::from_utf8(body.to_vec()).unwrap()));
+                        let res = match (&*method, path.trim_start_matches("/api/v4/projects/group%2Fjobs")) {
+                            ("POST", "/repository/commits") => serde_json::json!({ "id": "abc" }).to_string(),
+                            ("GET", "/pipelines/31") => serde_json::json!({
+                                "id": 31,
+                                "ref": "job-gh1",
+                                "sha": "abc",
+                                "status": "success",
+                                "web_url": "https://gitlab.example.com/group/jobs/-/pipelines/31",
+                            }).to_string(),
+                            ("GET", "/repository/commits/abc") => serde_json::json!({
+                                "message": commit_message,
+                            }).to_string(),
+                            ("GET", "/pipelines/31/jobs") => serde_json::json!([{ "id": 380, "name": "bisect" }]).to_string(),
+                            ("GET", "/jobs/380/artifacts/bisect-result.json") => {
+                                r#"{"schema_version":1,"status":"found","exit_code":0,"regressed_toolchain":"nightly-2020-04-19","commit":null,"stderr_tail":""}"#.to_string()
+                            }
+                            _ => return Ok::<_, std::convert::Infallible>(Response::builder().status(404).body(Body::empty()).unwrap()),
+                        };
+                        Ok(Response::new(Body::from(res)))
+                    }
+                }))
+            }
+        }))
+    };
+    let api_url = format!("http://{}/api/v4", server.local_addr());
+    tokio::spawn(server);
+
+    let (on_update, updates) = crate::runner::recording_callback();
+    let runner = GitlabRunner::new(
+        api_url,
+        "group/jobs".to_string(),
+        "api-token".to_string(),
+        "webhook-token".to_string(),
+        "main".to_string(),
+        on_update,
+    );
+
+    runner.submit(&crate::job::test_job("gh1")).await.unwrap();
+    {
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let (method, path, body) = &requests[0];
+        assert_eq!((&**method, &**path), ("POST", "/api/v4/projects/group%2Fjobs/repository/commits"));
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["branch"], "job-gh1");
+        assert_eq!(body["commit_message"], commit_message);
+        let paths = body["actions"].as_array().unwrap().iter().map(|action| action["file_path"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(paths, [".gitlab-ci.yml", "Cargo.toml", "src/lib.rs", "bisect-job.json", "run-bisect.sh"]);
+    }
+
+    let event = |token: &str, event: &str, project: &str| {
+        Request::post("/gitlab")
+            .header("X-Gitlab-Token", token)
+            .header("X-Gitlab-Event", event)
+            .body(Body::from(serde_json::json!({
+                "object_kind": "pipeline",
+                "object_attributes": { "id": 31, "ref": "job-gh1", "status": "success" },
+                "project": { "path_with_namespace": project },
+            }).to_string()))
+            .unwrap()
+    };
+    let res = runner.handle_request(event("wrong", "Pipeline Hook", "group/jobs")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    runner.handle_request(event("webhook-token", "Push Hook", "group/jobs")).await.unwrap();
+    runner.handle_request(event("webhook-token", "Pipeline Hook", "other/project")).await.unwrap();
+    assert!(updates.lock().unwrap().is_empty());
+
+    let res = runner.handle_request(event("webhook-token", "Pipeline Hook", "group/jobs")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let updates = updates.lock().unwrap();
+    match &updates[..] {
+        [(job_id, JobStatus::Completed { result: Some(result), url: Some(url), .. })] => {
+            assert_eq!(job_id, "gh1");
+            assert_eq!(result.regressed_toolchain.as_deref(), Some("nightly-2020-04-19"));
+            assert_eq!(url, "https://gitlab.example.com/group/jobs/-/pipelines/31");
+        }
+        updates => panic!("unexpected updates {:?}", updates),
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::job::Job;
+use crate::report::{BisectReport, BisectResult, BisectStatus};
+use crate::runner::{JobRunner, JobStatus, StatusCallback};
+
+/// Number of lines of output to include in the result of a failed job.
+const OUTPUT_TAIL_LINES: usize = 50;
+
+/// Environment variables passed on to jobs. Everything else, like the secrets of the bot, is
+/// withheld from the user supplied script.
+const JOB_ENV: &[&str] = &["PATH", "HOME", "RUSTUP_HOME", "CARGO_HOME"];
+
+/// Runs cargo-bisect-rustc as child process on the machine the bot runs on.
+#[derive(Clone)]
+pub(crate) struct LocalRunner {
+    /// The command to run, followed by its arguments. The job arguments are appended to this.
+    command: Vec<String>,
+    /// Directory the logs of all jobs are written to.
+    log_dir: PathBuf,
+    /// Maximum time a single job may run.
+    timeout: Duration,
+    /// Limits the number of concurrently running jobs.
+    slots: Arc<tokio::sync::Semaphore>,
+    /// Jobs which haven't finished yet.
+    jobs: Arc<Mutex<HashMap<String, LocalJob>>>,
+    on_update: StatusCallback,
+    /// Run the scripts of everyone rather than only those of maintainers.
+    allow_scripts: bool,
+}
+
+struct LocalJob {
+    status: JobStatus,
+    /// Stops the job when it is still running.
+    cancel: Option<tokio::sync::oneshot::Sender<()>>,
+}
+
+impl LocalRunner {
+    pub(crate) fn new(command: Vec<String>, log_dir: PathBuf, max_jobs: usize, timeout: Duration, on_update: StatusCallback) -> Self {
+        LocalRunner {
+            command,
+            log_dir,
+            timeout,
+            slots: Arc::new(tokio::sync::Semaphore::new(max_jobs)),
+            jobs: Default::default(),
+            on_update,
+            allow_scripts: false,
+        }
+    }
+
+    /// Configure the runner using `LOCAL_BISECT_COMMAND`, `LOCAL_LOG_DIR`, `LOCAL_MAX_JOBS`,
+    /// `LOCAL_JOB_TIMEOUT` (in seconds) and `LOCAL_ALLOW_SCRIPTS=1`.
+    pub(crate) fn from_env(on_update: StatusCallback) -> Self {
+        let command = std::env::var("LOCAL_BISECT_COMMAND").unwrap_or_else(|_| "cargo bisect-rustc".to_string());
+        let log_dir = std::env::var("LOCAL_LOG_DIR").unwrap_or_else(|_| "job-logs".to_string());
+        let max_jobs = std::env::var("LOCAL_MAX_JOBS").map(|n| n.parse().expect("invalid LOCAL_MAX_JOBS")).unwrap_or(1);
+        let timeout = std::env::var("LOCAL_JOB_TIMEOUT").map(|n| n.parse().expect("invalid LOCAL_JOB_TIMEOUT")).unwrap_or(2 * 60 * 60);
+        let mut runner = LocalRunner::new(
+            command.split_whitespace().map(|arg| arg.to_string()).collect(),
+            PathBuf::from(log_dir),
+            max_jobs,
+            Duration::from_secs(timeout),
+            on_update,
+        );
+        runner.allow_scripts = std::env::var("LOCAL_ALLOW_SCRIPTS").is_ok_and(|allow| allow == "1");
+        runner
+    }
+
+    /// Start the unfinished jobs of `store` again. Jobs which were running when the bot stopped
+    /// are run from the start.
+    pub(crate) fn requeue_active(&self, store: &dyn crate::store::JobStore) {
+        let records = match store.active_jobs() {
+            Ok(records) => records,
+            Err(err) => {
+                println!("failed to load unfinished jobs: {}", err);
+                return;
+            }
+        };
+        for record in records {
+            println!("requeueing job {}", record.spec.job_id);
+            self.start(Job {
+                spec: record.spec,
+                code: record.code,
+                script: record.script,
+            });
+        }
+    }
+
+    /// Run a job in the background, reporting its result through `on_update`.
+    fn start(&self, job: Job) {
+        let (cancel, cancelled) = tokio::sync::oneshot::channel();
+        self.jobs.lock().unwrap().insert(job.spec.job_id.clone(), LocalJob {
+            status: JobStatus::Queued,
+            cancel: Some(cancel),
+        });
+
+        let runner = self.clone();
+        tokio::spawn(async move {
+            let job_id = &job.spec.job_id;
+            let status = match runner.run_job(&job, cancelled).await {
+                Ok(result) => JobStatus::Completed {
+                    summary: crate::runner::render_result(&result).await,
+                    result: Some(result),
+                    url: None,
+                },
+                Err(err) => {
+                    let cancelled = runner.jobs.lock().unwrap().remove(job_id).is_some_and(|job| job.cancel.is_none());
+                    if cancelled {
+                        // Whoever cancelled the job reports that
+                        println!("job {} cancelled: {}", job_id, err);
+                        return;
+                    }
+                    println!("job {} failed: {}", job_id, err);
+                    JobStatus::Completed {
+                        result: None,
+                        summary: "The job could not be run.".to_string(),
+                        url: None,
+                    }
+                }
+            };
+            runner.jobs.lock().unwrap().remove(job_id);
+            (runner.on_update)(job_id.clone(), job.spec.reply_to.clone(), status).await;
+        });
+    }
+
+    pub(crate) fn log_file(&self, job_id: &str) -> PathBuf {
+        self.log_dir.join(format!("{}.log", job_id))
+    }
+
+    /// Returns whether the job is tracked by this runner, rather than run for a worker.
+    fn set_status(&self, job_id: &str, status: JobStatus) -> bool {
+        match self.jobs.lock().unwrap().get_mut(job_id) {
+            Some(job) => {
+                job.status = status;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run a job to completion, waiting for a free slot first.
+    pub(crate) async fn run_job(&self, job: &Job, mut cancel: tokio::sync::oneshot::Receiver<()>) -> Result<BisectResult, Box<dyn std::error::Error + Send + Sync>> {
+        let _slot = tokio::select! {
+            slot = self.slots.acquire() => slot,
+            _ = &mut cancel => return Err("job was cancelled".into()),
+        };
+        if self.set_status(&job.spec.job_id, JobStatus::Running { url: None }) {
+            (self.on_update)(job.spec.job_id.clone(), job.spec.reply_to.clone(), JobStatus::Running { url: None }).await;
+        }
+
+        let dir = tempfile::Builder::new().prefix("cargo-bisect-bot-").tempdir()?;
+        for (path, contents, executable) in job.files() {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(&path, contents)?;
+            if executable {
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+            }
+        }
+
+        std::fs::create_dir_all(&self.log_dir)?;
+        let log_file = self.log_file(&job.spec.job_id);
+        let log = std::fs::File::create(&log_file)?;
+        println!("running job {} in {}, logging to {}", job.spec.job_id, dir.path().display(), log_file.display());
+
+        let (program, args) = self.command.split_first().ok_or("empty bisect command")?;
+        let mut command = std::process::Command::new(program);
+        command
+            .args(args)
+            .args(&job.spec.args)
+            .arg("--access=github")
+            .current_dir(dir.path())
+            .env_clear()
+            .envs(JOB_ENV.iter().filter_map(|&key| Some((key, std::env::var_os(key)?))))
+            .stdin(std::process::Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            // Own process group, so that the user script can be killed together with the bisection
+            .process_group(0);
+        let mut child = tokio::process::Command::from(command).kill_on_drop(true).spawn()?;
+        let _kill_group = KillProcessGroup(child.id());
+
+        let exit_code = tokio::select! {
+            status = &mut child => Some(status?.code().unwrap_or(-1)),
+            _ = tokio::time::delay_for(self.timeout) => None,
+            _ = cancel => return Err("job was cancelled".into()),
+        };
+
+        let log = std::fs::read_to_string(&log_file)?;
+        let lines = log.lines().collect::<Vec<_>>();
+        let mut stderr_tail = lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..].join("\n");
+        let mut result = BisectReport::parse(&log).to_result();
+        match exit_code {
+            Some(0) => {}
+            Some(exit_code) => {
+                result.status = BisectStatus::Error;
+                result.exit_code = exit_code;
+            }
+            None => {
+                result.status = BisectStatus::Error;
+                result.exit_code = -1;
+                stderr_tail.push_str(&format!("\njob timed out after {} seconds", self.timeout.as_secs()));
+            }
+        }
+        result.stderr_tail = stderr_tail;
+        Ok(result)
+    }
+}
+
+/// Kills a process group when dropped, including processes which outlived its leader.
+struct KillProcessGroup(u32);
+
+impl Drop for KillProcessGroup {
+    fn drop(&mut self) {
+        // SAFETY: kill doesn't access memory. A negative pid targets the process group.
+        unsafe {
+            libc::kill(-(self.0 as libc::pid_t), libc::SIGKILL);
+        }
+    }
+}
+
+#[async_trait]
+impl JobRunner for LocalRunner {
+    async fn submit(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        crate::runner::check_script(job, self.allow_scripts)?;
+        self.start(job.clone());
+        Ok(())
+    }
+
+    async fn cancel(&self, job_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id).ok_or("unknown job")?;
+        let cancel = job.cancel.take().ok_or("job already cancelled")?;
+        // The job may already have finished, in which case there is nothing to cancel.
+        let _ = cancel.send(());
+        Ok(())
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.jobs.lock().unwrap().get(job_id).ok_or("unknown job")?.status.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn fake_bisect_rustc(dir: &std::path::Path, script: &str) -> Vec<String> {
+    let path = dir.join("fake-cargo-bisect-rustc");
+    std::fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    vec![path.to_str().unwrap().to_string()]
+}
+
+#[tokio::test]
+async fn test_local_runner() {
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), r#"
+test -f Cargo.toml && test -f src/lib.rs && test -f bisect-job.json || exit 2
+echo "args: $@"
+echo "searched nightlies: from nightly-2020-04-01 to nightly-2020-05-01"
+echo "regressed nightly: nightly-2020-04-19"
+echo "regressed commit: https://github.com/rust-lang/rust/commit/339f574809bf8e4166b8de3cdbe7df181d37af3d" >&2
+"#);
+    let runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), crate::runner::ignore_updates());
+    let job = crate::job::test_job("gh1");
+    let (_cancel, cancelled) = tokio::sync::oneshot::channel();
+    let result = runner.run_job(&job, cancelled).await.unwrap();
+    assert_eq!(result.status, BisectStatus::Found);
+    assert_eq!(result.regressed_toolchain.as_deref(), Some("nightly-2020-04-19"));
+    assert_eq!(result.commit.as_deref(), Some("339f574809bf8e4166b8de3cdbe7df181d37af3d"));
+
+    let log = std::fs::read_to_string(runner.log_file("gh1")).unwrap();
+    assert!(log.starts_with("args: --end=2020-05-01 --access=github\n"), "{}", log);
+}
+
+#[tokio::test]
+async fn test_local_runner_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), "echo 'ERROR: the start of the range must not reproduce the regression' >&2\nexit 1\n");
+    let runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), crate::runner::ignore_updates());
+    let (_cancel, cancelled) = tokio::sync::oneshot::channel();
+    let result = runner.run_job(&crate::job::test_job("gh1"), cancelled).await.unwrap();
+    assert_eq!(result.status, BisectStatus::Error);
+    assert_eq!(result.exit_code, 1);
+    assert_eq!(result.stderr_tail, "ERROR: the start of the range must not reproduce the regression");
+
+    let command = fake_bisect_rustc(dir.path(), "sleep 10\n");
+    let runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_millis(100), crate::runner::ignore_updates());
+    let (_cancel, cancelled) = tokio::sync::oneshot::channel();
+    let result = runner.run_job(&crate::job::test_job("gh2"), cancelled).await.unwrap();
+    assert_eq!(result.status, BisectStatus::Error);
+    assert!(result.stderr_tail.contains("job timed out"), "{}", result.stderr_tail);
+
+    let (cancel, cancelled) = tokio::sync::oneshot::channel();
+    cancel.send(()).unwrap();
+    assert!(runner.run_job(&crate::job::test_job("gh3"), cancelled).await.is_err());
+}
+
+#[tokio::test]
+async fn test_local_runner_env() {
+    std::env::set_var("BISECT_BOT_TEST_SECRET", "secret");
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), "test -z \"$BISECT_BOT_TEST_SECRET\" && test -n \"$PATH\" || exit 3\n");
+    let runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), crate::runner::ignore_updates());
+    let (_cancel, cancelled) = tokio::sync::oneshot::channel();
+    let result = runner.run_job(&crate::job::test_job("gh1"), cancelled).await.unwrap();
+    assert_eq!(result.exit_code, 0, "{}", result.stderr_tail);
+}
+
+#[tokio::test]
+async fn test_local_runner_concurrency_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), "sleep 0.2\n");
+    let runner = Arc::new(LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), crate::runner::ignore_updates()));
+    let start = std::time::Instant::now();
+    let run = |job_id: &'static str| {
+        let runner = runner.clone();
+        async move {
+            let (_cancel, cancelled) = tokio::sync::oneshot::channel();
+            runner.run_job(&crate::job::test_job(job_id), cancelled).await.unwrap()
+        }
+    };
+    futures::future::join(run("gh1"), run("gh2")).await;
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[cfg(test)]
+async fn wait_for_updates(updates: &crate::runner::Updates, count: usize) -> Vec<(String, JobStatus)> {
+    for _ in 0..100 {
+        let updates = updates.lock().unwrap().clone();
+        if updates.len() >= count {
+            return updates;
+        }
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+    }
+    panic!("no status update");
+}
+
+#[tokio::test]
+async fn test_local_runner_submit() {
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), "echo 'regressed nightly: nightly-2020-04-19'\n");
+    let (on_update, updates) = crate::runner::recording_callback();
+    let runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), on_update);
+    runner.submit(&crate::job::test_job("gh1")).await.unwrap();
+    assert!(matches!(runner.status("gh1").await.unwrap(), JobStatus::Queued | JobStatus::Running { .. }));
+
+    let updates = wait_for_updates(&updates, 2).await;
+    assert_eq!(updates[0], ("gh1".to_string(), JobStatus::Running { url: None }));
+    match &updates[1] {
+        (job_id, JobStatus::Completed { result: Some(result), .. }) => {
+            assert_eq!(job_id, "gh1");
+            assert_eq!(result.regressed_toolchain.as_deref(), Some("nightly-2020-04-19"));
+        }
+        update => panic!("unexpected update {:?}", update),
+    }
+    assert!(runner.status("gh1").await.is_err());
+}
+
+#[tokio::test]
+async fn test_local_runner_cancel() {
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), "sleep 10\n");
+    let (on_update, updates) = crate::runner::recording_callback();
+    let runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), on_update);
+    runner.submit(&crate::job::test_job("gh1")).await.unwrap();
+    runner.submit(&crate::job::test_job("gh2")).await.unwrap();
+    tokio::time::delay_for(Duration::from_millis(100)).await;
+    assert_eq!(runner.status("gh1").await.unwrap(), JobStatus::Running { url: None });
+    assert_eq!(runner.status("gh2").await.unwrap(), JobStatus::Queued);
+
+    // Both while waiting for a slot and while running
+    runner.cancel("gh2").await.unwrap();
+    runner.cancel("gh1").await.unwrap();
+    assert!(runner.cancel("gh1").await.is_err());
+    tokio::time::delay_for(Duration::from_millis(200)).await;
+    assert!(runner.status("gh1").await.is_err());
+    assert!(runner.status("gh2").await.is_err());
+    // Cancellations are reported by the caller
+    assert_eq!(*updates.lock().unwrap(), vec![("gh1".to_string(), JobStatus::Running { url: None })]);
+}
+
+#[tokio::test]
+async fn test_local_runner_requeue_active() {
+    use crate::store::{JobState, JobStore};
+
+    let store = crate::store::SqliteStore::open(":memory:").unwrap();
+    for job_id in &["gh1", "gh2"] {
+        store.insert(&crate::job::test_job(job_id)).unwrap();
+    }
+    store.transition("gh1", JobState::Running, None).unwrap();
+    store.transition("gh2", JobState::Cancelled, None).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), "exit 0\n");
+    let (on_update, updates) = crate::runner::recording_callback();
+    let runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), on_update);
+    runner.requeue_active(&store);
+    assert!(runner.status("gh2").await.is_err());
+    let updates = wait_for_updates(&updates, 2).await;
+    assert!(matches!(&updates[1], (job_id, JobStatus::Completed { .. }) if job_id == "gh1"));
+}
+
+#[tokio::test]
+async fn test_local_runner_rejects_scripts() {
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_bisect_rustc(dir.path(), "exit 0\n");
+    let mut runner = LocalRunner::new(command, dir.path().join("logs"), 1, Duration::from_secs(10), crate::runner::ignore_updates());
+    let mut job = crate::job::test_job("gh1");
+    job.script = Some("#!/bin/sh\ncargo build".to_string());
+    let err = runner.submit(&job).await.unwrap_err();
+    assert!(err.is::<crate::runner::Rejected>(), "{}", err);
+    assert!(runner.status("gh1").await.is_err());
+
+    runner.allow_scripts = true;
+    runner.submit(&job).await.unwrap();
+}
@@ -0,0 +1,508 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use crate::job::{Job, JobSpec};
+use crate::report::BisectResult;
+use crate::runner::{response, JobRunner, JobStatus, StatusCallback};
+
+/// Hands out jobs to self-hosted workers polling the bot over HTTP.
+///
+/// The worker API lives under `/worker/` and requires `Authorization: Bearer <WORKER_TOKEN>`:
+///
+/// * `POST /worker/lease` with the worker name as body leases the next queued job. Returns a
+///   [`LeasedJob`] or `204 No Content` when there is nothing to do.
+/// * `POST /worker/leases/{lease_id}/heartbeat` keeps the lease alive.
+/// * `POST /worker/leases/{lease_id}/log` appends the body to the job log and keeps the lease alive.
+/// * `POST /worker/leases/{lease_id}/result` finishes the job with a [`BisectResult`].
+///
+/// Leases which are not kept alive expire and their job is queued again. Requests for an expired
+/// or cancelled lease return `410 Gone`, after which the worker should stop working on the job.
+pub(crate) struct WorkerRunner {
+    token: String,
+    lease_duration: Duration,
+    log_dir: PathBuf,
+    on_update: StatusCallback,
+    /// Hand out the scripts of everyone rather than only those of maintainers.
+    allow_scripts: bool,
+    queue: Mutex<Queue>,
+}
+
+#[derive(Default)]
+struct Queue {
+    queued: VecDeque<Job>,
+    leases: HashMap<String, Lease>,
+    statuses: HashMap<String, JobStatus>,
+    next_lease: u64,
+}
+
+struct Lease {
+    job: Job,
+    worker: String,
+    expires: Instant,
+}
+
+/// A job handed out to a worker.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct LeasedJob {
+    pub(crate) lease_id: String,
+    /// The lease expires if no heartbeat is sent within this many seconds.
+    pub(crate) lease_seconds: u64,
+    pub(crate) job: JobSpec,
+    pub(crate) code: String,
+    pub(crate) script: Option<String>,
+}
+
+impl Queue {
+    /// Put the jobs of all expired leases back at the front of the queue and return them.
+    fn requeue_expired(&mut self, now: Instant) -> Vec<Job> {
+        let expired = self.leases.iter()
+            .filter(|(_, lease)| lease.expires <= now)
+            .map(|(lease_id, _)| lease_id.clone())
+            .collect::<Vec<_>>();
+        let mut requeued = Vec::new();
+        for lease_id in expired {
+            let lease = self.leases.remove(&lease_id).unwrap();
+            println!("lease {} of {} expired, requeueing job {}", lease_id, lease.worker, lease.job.spec.job_id);
+            self.statuses.insert(lease.job.spec.job_id.clone(), JobStatus::Queued);
+            requeued.push(lease.job.clone());
+            self.queued.push_front(lease.job);
+        }
+        requeued
+    }
+}
+
+impl WorkerRunner {
+    pub(crate) fn new(token: String, lease_duration: Duration, log_dir: PathBuf, on_update: StatusCallback) -> Self {
+        WorkerRunner {
+            token,
+            lease_duration,
+            log_dir,
+            on_update,
+            allow_scripts: false,
+            queue: Default::default(),
+        }
+    }
+
+    /// Configure the runner using `WORKER_TOKEN`, `WORKER_LEASE_SECONDS`, `WORKER_LOG_DIR` and
+    /// `WORKER_ALLOW_SCRIPTS=1`.
+    pub(crate) fn from_env() -> Self {
+        let token = std::env::var("WORKER_TOKEN").expect("worker token not defined");
+        let lease_seconds = std::env::var("WORKER_LEASE_SECONDS").map(|n| n.parse().expect("invalid WORKER_LEASE_SECONDS")).unwrap_or(300);
+        let log_dir = std::env::var("WORKER_LOG_DIR").unwrap_or_else(|_| "job-logs".to_string());
+        let mut runner = WorkerRunner::new(token, Duration::from_secs(lease_seconds), PathBuf::from(log_dir), crate::runner::notify_users());
+        runner.allow_scripts = std::env::var("WORKER_ALLOW_SCRIPTS").is_ok_and(|allow| allow == "1");
+        runner.requeue_active(&**crate::JOB_STORE);
+        runner
+    }
+
+    /// Queue the unfinished jobs of `store` again. The queue and all leases are lost on restart,
+    /// while workers stop working on a job once its lease is gone.
+    fn requeue_active(&self, store: &dyn crate::store::JobStore) {
+        let records = match store.active_jobs() {
+            Ok(records) => records,
+            Err(err) => {
+                println!("failed to load unfinished jobs: {}", err);
+                return;
+            }
+        };
+        let mut queue = self.queue.lock().unwrap();
+        for record in records {
+            println!("requeueing job {}", record.spec.job_id);
+            queue.statuses.insert(record.spec.job_id.clone(), JobStatus::Queued);
+            queue.queued.push_back(Job {
+                spec: record.spec,
+                code: record.code,
+                script: record.script,
+            });
+        }
+    }
+
+    /// Requeue the jobs of expired leases and report them as queued again.
+    async fn expire_leases(&self) {
+        let requeued = self.queue.lock().unwrap().requeue_expired(Instant::now());
+        for job in requeued {
+            (self.on_update)(job.spec.job_id.clone(), job.spec.reply_to.clone(), JobStatus::Queued).await;
+        }
+    }
+
+    fn lease(&self, worker: &str) -> Option<LeasedJob> {
+        let mut queue = self.queue.lock().unwrap();
+        let job = queue.queued.pop_front()?;
+        queue.next_lease += 1;
+        let lease_id = format!("{}-{}", job.spec.job_id, queue.next_lease);
+        println!("leased job {} to {} as {}", job.spec.job_id, worker, lease_id);
+        queue.statuses.insert(job.spec.job_id.clone(), JobStatus::Running { url: None });
+        let leased = LeasedJob {
+            lease_id: lease_id.clone(),
+            lease_seconds: self.lease_duration.as_secs(),
+            job: job.spec.clone(),
+            code: job.code.clone(),
+            script: job.script.clone(),
+        };
+        queue.leases.insert(lease_id, Lease {
+            job,
+            worker: worker.to_string(),
+            expires: Instant::now() + self.lease_duration,
+        });
+        Some(leased)
+    }
+
+    /// Extend a lease. Returns the job it is for, or `None` if the lease is gone.
+    fn heartbeat(&self, lease_id: &str) -> Option<Job> {
+        let mut queue = self.queue.lock().unwrap();
+        let lease = queue.leases.get_mut(lease_id)?;
+        lease.expires = Instant::now() + self.lease_duration;
+        Some(lease.job.clone())
+    }
+
+    fn append_log(&self, job_id: &str, chunk: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.log_dir)?;
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.log_dir.join(format!("{}.log", job_id)))?
+            .write_all(chunk)
+    }
+
+    async fn update(&self, job: &Job, status: JobStatus) {
+        self.queue.lock().unwrap().statuses.insert(job.spec.job_id.clone(), status.clone());
+        (self.on_update)(job.spec.job_id.clone(), job.spec.reply_to.clone(), status).await;
+    }
+
+    async fn lease_request(&self, lease_id: &str, action: &str, body: &[u8]) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+        let job = match self.heartbeat(lease_id) {
+            Some(job) => job,
+            None => return response(StatusCode::GONE, "lease expired or job cancelled"),
+        };
+        match action {
+            "heartbeat" => {}
+            "log" => self.append_log(&job.spec.job_id, body)?,
+            "result" => {
+                let result: BisectResult = match serde_json::from_slice(body) {
+                    Ok(result) => result,
+                    Err(err) => return response(StatusCode::BAD_REQUEST, format!("invalid result: {}", err)),
+                };
+                self.queue.lock().unwrap().leases.remove(lease_id);
+                let summary = crate::runner::render_result(&result).await;
+                self.update(&job, JobStatus::Completed { result: Some(result), summary, url: None }).await;
+            }
+            _ => return response(StatusCode::NOT_FOUND, "unknown lease action"),
+        }
+        response(StatusCode::OK, "ok")
+    }
+}
+
+#[async_trait]
+impl JobRunner for WorkerRunner {
+    async fn submit(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        crate::runner::check_script(job, self.allow_scripts)?;
+        let mut queue = self.queue.lock().unwrap();
+        queue.statuses.insert(job.spec.job_id.clone(), JobStatus::Queued);
+        queue.queued.push_back(job.clone());
+        Ok(())
+    }
+
+    async fn cancel(&self, job_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut queue = self.queue.lock().unwrap();
+        let job = if let Some(pos) = queue.queued.iter().position(|job| job.spec.job_id == job_id) {
+            queue.queued.remove(pos)
+        } else {
+            // The worker will notice on its next heartbeat
+            let lease_id = queue.leases.iter()
+                .find(|(_, lease)| lease.job.spec.job_id == job_id)
+                .map(|(lease_id, _)| lease_id.clone());
+            lease_id.and_then(|lease_id| queue.leases.remove(&lease_id)).map(|lease| lease.job)
+        };
+        // The cancellation is reported by the caller
+        let job = job.ok_or("job is not queued or running")?;
+        queue.statuses.insert(job.spec.job_id, JobStatus::Cancelled);
+        Ok(())
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, Box<dyn std::error::Error + Send + Sync>> {
+        self.expire_leases().await;
+        Ok(self.queue.lock().unwrap().statuses.get(job_id).ok_or("unknown job")?.clone())
+    }
+
+    fn http_prefix(&self) -> Option<&'static str> {
+        Some("/worker/")
+    }
+
+    async fn handle_request(&self, req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+        let authorized = req.headers().get(hyper::header::AUTHORIZATION)
+            .and_then(|auth| auth.to_str().ok()?.strip_prefix("Bearer "))
+            .is_some_and(|token| crate::runner::token_matches(&self.token, token.as_bytes()));
+        if !authorized {
+            println!("unauthorized worker request to {}", req.uri().path());
+            return response(StatusCode::UNAUTHORIZED, "unauthorized");
+        }
+        if req.method() != Method::POST {
+            return response(StatusCode::METHOD_NOT_ALLOWED, "only POST is supported");
+        }
+
+        self.expire_leases().await;
+        let path = req.uri().path().trim_start_matches("/worker/").to_string();
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+        let parts = path.split('/').collect::<Vec<_>>();
+        match &*parts {
+            ["lease"] => {
+                let worker = std::str::from_utf8(&body)?.trim();
+                let leased = match self.lease(if worker.is_empty() { "unnamed worker" } else { worker }) {
+                    Some(leased) => leased,
+                    None => return response(StatusCode::NO_CONTENT, Body::empty()),
+                };
+                let job = Job {
+                    spec: leased.job.clone(),
+                    code: leased.code.clone(),
+                    script: leased.script.clone(),
+                };
+                self.update(&job, JobStatus::Running { url: None }).await;
+                response(StatusCode::OK, serde_json::to_string(&leased)?)
+            }
+            ["leases", lease_id, action] => self.lease_request(lease_id, action, &body).await,
+            _ => response(StatusCode::NOT_FOUND, "unknown worker endpoint"),
+        }
+    }
+}
+
+/// Client side of the worker protocol. Leases jobs from `server` and runs them using `executor`.
+pub(crate) async fn run_worker(server: &str, token: &str, name: &str, executor: &crate::runner::local::LocalRunner) {
+    let client = reqwest::Client::new();
+    loop {
+        match work_once(&client, server, token, name, executor).await {
+            Ok(true) => {}
+            Ok(false) => tokio::time::delay_for(Duration::from_secs(30)).await,
+            Err(err) => {
+                println!("worker error: {}", err);
+                tokio::time::delay_for(Duration::from_secs(30)).await;
+            }
+        }
+    }
+}
+
+/// Lease and run a single job. Returns `false` if there was no job to run.
+async fn work_once(
+    client: &reqwest::Client,
+    server: &str,
+    token: &str,
+    name: &str,
+    executor: &crate::runner::local::LocalRunner,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let res = client.post(&format!("{}/worker/lease", server))
+        .bearer_auth(token)
+        .body(name.to_string())
+        .send().await?
+        .error_for_status()?;
+    if res.status() == reqwest::StatusCode::NO_CONTENT {
+        return Ok(false);
+    }
+    let leased: LeasedJob = serde_json::from_str(&res.text().await?)?;
+    let job = Job {
+        spec: leased.job,
+        code: leased.code,
+        script: leased.script,
+    };
+    let lease_url = format!("{}/worker/leases/{}", server, leased.lease_id);
+    println!("working on job {} with lease {}", job.spec.job_id, leased.lease_id);
+
+    // Sends all output written since the last call as log chunk. This also keeps the lease alive.
+    let log_file = executor.log_file(&job.spec.job_id);
+    let mut log_offset = 0;
+    let mut send_log = move || {
+        let mut chunk = Vec::new();
+        if let Ok(mut log) = std::fs::File::open(&log_file) {
+            log.seek(SeekFrom::Start(log_offset))?;
+            log.read_to_end(&mut chunk)?;
+            log_offset += chunk.len() as u64;
+        }
+        let req = client.post(&format!("{}/log", lease_url)).bearer_auth(token).body(chunk);
+        Ok::<_, std::io::Error>(async move { req.send().await?.error_for_status() })
+    };
+
+    let (cancel, cancelled) = tokio::sync::oneshot::channel();
+    let run = executor.run_job(&job, cancelled);
+    futures::pin_mut!(run);
+    let heartbeat_interval = Duration::from_secs((leased.lease_seconds / 3).max(1));
+    let mut cancel = Some(cancel);
+    let result = loop {
+        tokio::select! {
+            result = &mut run => break result,
+            _ = tokio::time::delay_for(heartbeat_interval) => {
+                match send_log()?.await {
+                    Err(err) if err.status() == Some(reqwest::StatusCode::GONE) => {
+                        println!("lost lease {}, stopping job {}", leased.lease_id, job.spec.job_id);
+                        if let Some(cancel) = cancel.take() {
+                            let _ = cancel.send(());
+                        }
+                    }
+                    Err(err) => println!("failed to send log of job {}: {}", job.spec.job_id, err),
+                    Ok(_) => {}
+                }
+            }
+        }
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(_) if cancel.is_none() => return Ok(true),
+        Err(err) => {
+            println!("failed to run job {}: {}", job.spec.job_id, err);
+            BisectResult {
+                schema_version: 1,
+                status: crate::report::BisectStatus::Error,
+                exit_code: -1,
+                searched_nightlies: None,
+                regressed_toolchain: None,
+                searched_commits: None,
+                commit: None,
+                stderr_tail: format!("worker {} failed to run the job", name),
+            }
+        }
+    };
+    send_log()?.await?;
+    client.post(&format!("{}/worker/leases/{}/result", server, leased.lease_id))
+        .bearer_auth(token)
+        .body(serde_json::to_string(&result)?)
+        .send().await?
+        .error_for_status()?;
+    Ok(true)
+}
+
+#[cfg(test)]
+fn test_worker_runner(lease_duration: Duration, log_dir: PathBuf) -> (std::sync::Arc<WorkerRunner>, crate::runner::Updates) {
+    let (on_update, updates) = crate::runner::recording_callback();
+    (std::sync::Arc::new(WorkerRunner::new("secret".to_string(), lease_duration, log_dir, on_update)), updates)
+}
+
+#[cfg(test)]
+async fn post(runner: &WorkerRunner, token: &str, path: &str, body: &str) -> (StatusCode, String) {
+    let req = Request::post(path)
+        .header(hyper::header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let res = runner.handle_request(req).await.unwrap();
+    let status = res.status();
+    (status, String::from_utf8(hyper::body::to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_worker_lease_expiry() {
+    let dir = tempfile::tempdir().unwrap();
+    let (runner, updates) = test_worker_runner(Duration::from_millis(100), dir.path().to_path_buf());
+
+    assert_eq!(post(&runner, "wrong", "/worker/lease", "w1").await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(post(&runner, "secret", "/worker/lease", "w1").await.0, StatusCode::NO_CONTENT);
+
+    runner.submit(&crate::job::test_job("gh1")).await.unwrap();
+    let (status, body) = post(&runner, "secret", "/worker/lease", "w1").await;
+    assert_eq!(status, StatusCode::OK);
+    let first: LeasedJob = serde_json::from_str(&body).unwrap();
+    assert_eq!(first.job.job_id, "gh1");
+    assert_eq!(runner.status("gh1").await.unwrap(), JobStatus::Running { url: None });
+    assert_eq!(post(&runner, "secret", "/worker/lease", "w2").await.0, StatusCode::NO_CONTENT);
+
+    // w1 dies, so the job is handed to w2 once the lease expires
+    tokio::time::delay_for(Duration::from_millis(150)).await;
+    assert_eq!(runner.status("gh1").await.unwrap(), JobStatus::Queued);
+    assert_eq!(updates.lock().unwrap().last().unwrap(), &("gh1".to_string(), JobStatus::Queued));
+    let (status, body) = post(&runner, "secret", "/worker/lease", "w2").await;
+    assert_eq!(status, StatusCode::OK);
+    let second: LeasedJob = serde_json::from_str(&body).unwrap();
+    assert_eq!(second.job.job_id, "gh1");
+    assert_ne!(first.lease_id, second.lease_id);
+
+    let heartbeat = |lease_id: &str| format!("/worker/leases/{}/heartbeat", lease_id);
+    assert_eq!(post(&runner, "secret", &heartbeat(&first.lease_id), "").await.0, StatusCode::GONE);
+    assert_eq!(post(&runner, "secret", &heartbeat(&second.lease_id), "").await.0, StatusCode::OK);
+
+    runner.cancel("gh1").await.unwrap();
+    assert_eq!(post(&runner, "secret", &heartbeat(&second.lease_id), "").await.0, StatusCode::GONE);
+    assert_eq!(runner.status("gh1").await.unwrap(), JobStatus::Cancelled);
+    assert!(!updates.lock().unwrap().iter().any(|(_, status)| *status == JobStatus::Cancelled));
+}
+
+#[tokio::test]
+async fn test_worker_rejects_scripts() {
+    let dir = tempfile::tempdir().unwrap();
+    let (runner, _updates) = test_worker_runner(Duration::from_secs(3), dir.path().to_path_buf());
+    let mut job = crate::job::test_job("gh1");
+    job.script = Some("#!/bin/sh\ncargo build".to_string());
+    let err = runner.submit(&job).await.unwrap_err();
+    assert!(err.is::<crate::runner::Rejected>(), "{}", err);
+    assert_eq!(post(&runner, "secret", "/worker/lease", "w1").await.0, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_worker_requeue_active() {
+    use crate::store::{JobState, JobStore};
+
+    let store = crate::store::SqliteStore::open(":memory:").unwrap();
+    for job_id in &["gh1", "gh2", "gh3"] {
+        store.insert(&crate::job::test_job(job_id)).unwrap();
+    }
+    store.transition("gh2", JobState::Running, None).unwrap();
+    store.transition("gh3", JobState::Cancelled, None).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let (runner, _updates) = test_worker_runner(Duration::from_secs(3), dir.path().to_path_buf());
+    runner.requeue_active(&store);
+    assert_eq!(runner.status("gh2").await.unwrap(), JobStatus::Queued);
+    assert!(runner.status("gh3").await.is_err());
+    let leased = |body: &str| serde_json::from_str::<LeasedJob>(body).unwrap().job.job_id;
+    assert_eq!(leased(&post(&runner, "secret", "/worker/lease", "w1").await.1), "gh1");
+    assert_eq!(leased(&post(&runner, "secret", "/worker/lease", "w1").await.1), "gh2");
+    assert_eq!(post(&runner, "secret", "/worker/lease", "w1").await.0, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_worker_end_to_end() {
+    use hyper::service::{make_service_fn, service_fn};
+
+    let dir = tempfile::tempdir().unwrap();
+    let (runner, updates) = test_worker_runner(Duration::from_secs(3), dir.path().join("server-logs"));
+    let server = {
+        let runner = runner.clone();
+        hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service_fn(move |_conn| {
+            let runner = runner.clone();
+            async move {
+                Ok::<_, std::convert::Infallible>(service_fn(move |req| {
+                    let runner = runner.clone();
+                    async move { runner.handle_request(req).await }
+                }))
+            }
+        }))
+    };
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    let command = crate::runner::local::fake_bisect_rustc(dir.path(), r#"
+echo "bisecting"
+sleep 1.5
+echo "regressed nightly: nightly-2020-04-19"
+"#);
+    let executor = crate::runner::local::LocalRunner::new(command, dir.path().join("worker-logs"), 1, Duration::from_secs(10), crate::runner::ignore_updates());
+    let client = reqwest::Client::new();
+
+    assert!(!work_once(&client, &url, "secret", "w1", &executor).await.unwrap());
+    runner.submit(&crate::job::test_job("gh1")).await.unwrap();
+    assert!(work_once(&client, &url, "secret", "w1", &executor).await.unwrap());
+
+    let updates = updates.lock().unwrap();
+    assert_eq!(updates[0], ("gh1".to_string(), JobStatus::Running { url: None }));
+    match &updates[1] {
+        (job_id, JobStatus::Completed { result: Some(result), .. }) => {
+            assert_eq!(job_id, "gh1");
+            assert_eq!(result.status, crate::report::BisectStatus::Found);
+            assert_eq!(result.regressed_toolchain.as_deref(), Some("nightly-2020-04-19"));
+        }
+        update => panic!("unexpected update {:?}", update),
+    }
+    let log = std::fs::read_to_string(dir.path().join("server-logs/gh1.log")).unwrap();
+    assert!(log.starts_with("bisecting\n"), "{}", log);
+    assert!(log.ends_with("regressed nightly: nightly-2020-04-19\n"), "{}", log);
+}
@@ -0,0 +1,585 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+
+use crate::job::{Job, JobSpec};
+use crate::report::{BisectResult, BisectStatus};
+use crate::runner::JobStatus;
+use crate::ReplyTo;
+
+/// Where a job is in its lifecycle, as recorded in the [`JobStore`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum JobState {
+    /// The command was parsed, but the job wasn't handed to the runner yet.
+    Created,
+    /// The runner accepted the job.
+    Queued,
+    Running,
+    Completed,
+    Cancelled,
+    /// The runner refused the job.
+    Failed,
+}
+
+impl JobState {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            JobState::Created => "created",
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Completed => "completed",
+            JobState::Cancelled => "cancelled",
+            JobState::Failed => "failed",
+        }
+    }
+
+    pub(crate) fn parse(state: &str) -> Option<Self> {
+        Some(match state {
+            "created" => JobState::Created,
+            "queued" => JobState::Queued,
+            "running" => JobState::Running,
+            "completed" => JobState::Completed,
+            "cancelled" => JobState::Cancelled,
+            "failed" => JobState::Failed,
+            _ => return None,
+        })
+    }
+
+    /// Whether the job may still be queued or running.
+    pub(crate) fn is_active(self) -> bool {
+        matches!(self, JobState::Created | JobState::Queued | JobState::Running)
+    }
+
+    pub(crate) fn from_status(status: &JobStatus) -> Self {
+        match status {
+            JobStatus::Queued => JobState::Queued,
+            JobStatus::Running { .. } => JobState::Running,
+            JobStatus::Completed { .. } => JobState::Completed,
+            JobStatus::Cancelled => JobState::Cancelled,
+        }
+    }
+}
+
+/// Everything the bot knows about a job.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct JobRecord {
+    pub(crate) spec: JobSpec,
+    /// Hash of the repro, see [`Job::repro_hash`].
+    pub(crate) repro_hash: String,
+    /// The branch the job was pushed to, if the runner uses one.
+    pub(crate) branch: Option<String>,
+    /// The GitHub check run of the job, if it runs on GitHub Actions.
+    pub(crate) check_run_id: Option<u64>,
+    pub(crate) state: JobState,
+    /// Every state the job has been in, oldest first.
+    pub(crate) transitions: Vec<(DateTime<Utc>, JobState)>,
+    pub(crate) result: Option<BisectResult>,
+    /// Contents of `src/lib.rs`.
+    pub(crate) code: String,
+    pub(crate) script: Option<String>,
+    /// Every thread the status of the job is reported to, starting with the one it was requested
+    /// in.
+    pub(crate) targets: Vec<JobTarget>,
+}
+
+/// A thread the status of a job is reported to.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct JobTarget {
+    pub(crate) reply_to: ReplyTo,
+    /// The message showing the status of the job, as returned by [`ReplyTo::comment`].
+    pub(crate) status_message: Option<String>,
+}
+
+impl JobRecord {
+    /// The threads the job reports to besides the one it was requested in.
+    pub(crate) fn extra_targets(&self) -> Vec<ReplyTo> {
+        self.targets.iter()
+            .map(|target| target.reply_to.clone())
+            .filter(|reply_to| *reply_to != self.spec.reply_to)
+            .collect()
+    }
+
+    /// Link to the check run of the job, if it has one.
+    pub(crate) fn check_run_url(&self) -> Option<String> {
+        self.check_run_id.map(|id| format!("https://github.com/{}/runs/{}", crate::JOB_REPO, id))
+    }
+
+    /// Describe the job as markdown list item. `status` is the live status according to the
+    /// runner, if known.
+    pub(crate) fn describe(&self, now: DateTime<Utc>, status: Option<&JobStatus>) -> String {
+        let since = self.transitions.last().map_or(self.spec.created_at, |&(at, _)| at);
+        let state = match status.map(JobState::from_status).unwrap_or(self.state) {
+            JobState::Created | JobState::Queued => format!("queued for {}", format_age(now - since)),
+            JobState::Running => format!("running for {}", format_age(now - since)),
+            JobState::Completed => {
+                let outcome = match self.result.as_ref().map(|result| result.status) {
+                    Some(BisectStatus::Found) => "regression found",
+                    Some(BisectStatus::NotFound) => "no regression found",
+                    Some(BisectStatus::Error) => "failed",
+                    None => "result unknown",
+                };
+                format!("completed {} ago ({})", format_age(now - since), outcome)
+            }
+            JobState::Cancelled => format!("cancelled {} ago", format_age(now - since)),
+            JobState::Failed => "could not be queued".to_string(),
+        };
+        let url = match status {
+            Some(JobStatus::Running { url: Some(url) }) | Some(JobStatus::Completed { url: Some(url), .. }) => Some(url.clone()),
+            _ => self.check_run_url(),
+        };
+        let mut line = format!(
+            "- `{}` requested {} ago by {}: {}",
+            self.spec.job_id,
+            format_age(now - self.spec.created_at),
+            self.spec.requester,
+            state,
+        );
+        if let Some(url) = url {
+            line.push_str(&format!(", {}", url));
+        }
+        line
+    }
+}
+
+/// Format a duration like `5 minutes`.
+pub(crate) fn format_age(age: chrono::Duration) -> String {
+    let (count, unit) = if age.num_days() > 0 {
+        (age.num_days(), "day")
+    } else if age.num_hours() > 0 {
+        (age.num_hours(), "hour")
+    } else if age.num_minutes() > 0 {
+        (age.num_minutes(), "minute")
+    } else {
+        return "less than a minute".to_string();
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+/// Persistent record of all jobs.
+pub(crate) trait JobStore: Send + Sync {
+    /// Record a new job in the [`JobState::Created`] state, reporting to the thread it was
+    /// requested in and the ones it notifies.
+    fn insert(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    fn set_branch(&self, job_id: &str, branch: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    fn set_check_run(&self, job_id: &str, check_run_id: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Report the status of a job to another thread. Returns `false` if it already was.
+    fn add_target(&self, job_id: &str, reply_to: &ReplyTo) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn set_status_message(&self, job_id: &str, reply_to: &ReplyTo, message_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Move a job to a new state. The result is only recorded if one is given.
+    fn transition(&self, job_id: &str, state: JobState, result: Option<&BisectResult>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    fn get(&self, job_id: &str) -> Result<Option<JobRecord>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// The last `limit` jobs reporting to `reply_to`, newest first. This includes jobs requested
+    /// elsewhere which notify or were subscribed from `reply_to`.
+    fn jobs_for(&self, reply_to: &ReplyTo, limit: usize) -> Result<Vec<JobRecord>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// All jobs which haven't finished yet, oldest first.
+    fn active_jobs(&self) -> Result<Vec<JobRecord>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// How many jobs were created for a comment.
+    fn attempts(&self, comment_id: &str) -> Result<u32, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Remember a webhook delivery. Returns `false` if it was already seen before. Deliveries older
+    /// than [`DELIVERY_RETENTION_DAYS`] are forgotten.
+    fn first_delivery(&self, delivery_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Forget a webhook delivery which couldn't be processed, so that it can be redelivered.
+    fn forget_delivery(&self, delivery_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// GitHub only allows redelivering webhook events of the last 3 days, so older deliveries don't
+/// need to be remembered.
+const DELIVERY_RETENTION_DAYS: i64 = 7;
+
+/// Open the SQLite database at `JOB_DB`. There is no default outside of tests, as a database in
+/// the working directory would be lost on every deploy to an ephemeral filesystem like Heroku's.
+pub(crate) fn from_env() -> Box<dyn JobStore> {
+    let path = match std::env::var("JOB_DB") {
+        Ok(path) => path,
+        // Tests must not write to the real database
+        Err(_) if cfg!(test) => ":memory:".to_string(),
+        Err(_) => panic!("job database not defined, set JOB_DB to a path on persistent storage"),
+    };
+    Box::new(SqliteStore::open(&path).expect("failed to open job database"))
+}
+
+/// A [`JobStore`] backed by SQLite.
+pub(crate) struct SqliteStore {
+    conn: Mutex<rusqlite::Connection>,
+}
+
+/// The migrations bringing the database to the latest schema. `PRAGMA user_version` is the number
+/// of migrations which have been applied. Never change a migration after it has been released.
+const MIGRATIONS: &[&str] = &["
+CREATE TABLE jobs (
+    id TEXT PRIMARY KEY NOT NULL,
+    comment_id TEXT NOT NULL,
+    spec TEXT NOT NULL,
+    repro_hash TEXT NOT NULL,
+    branch TEXT,
+    check_run_id INTEGER,
+    state TEXT NOT NULL,
+    result TEXT,
+    code TEXT NOT NULL,
+    script TEXT
+);
+CREATE INDEX jobs_comment_id ON jobs (comment_id);
+CREATE TABLE job_transitions (
+    job_id TEXT NOT NULL REFERENCES jobs (id),
+    state TEXT NOT NULL,
+    at TEXT NOT NULL
+);
+CREATE INDEX job_transitions_job_id ON job_transitions (job_id);
+CREATE TABLE job_targets (
+    job_id TEXT NOT NULL REFERENCES jobs (id),
+    reply_to TEXT NOT NULL,
+    status_message TEXT,
+    PRIMARY KEY (job_id, reply_to)
+);
+CREATE TABLE deliveries (
+    id TEXT PRIMARY KEY NOT NULL,
+    received_at TEXT NOT NULL
+);
+"];
+
+impl SqliteStore {
+    pub(crate) fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = rusqlite::Connection::open(path)?;
+        let version: usize = conn.query_row("PRAGMA user_version", rusqlite::params![], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(&format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", migration, i + 1))?;
+        }
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+
+    /// Run an `UPDATE` of a single job, failing if the job doesn't exist.
+    fn update(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.conn.lock().unwrap().execute(sql, params)? {
+            0 => Err("unknown job".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl JobStore for SqliteStore {
+    fn insert(&self, job: &Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO jobs (id, comment_id, spec, repro_hash, state, code, script) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![
+                job.spec.job_id,
+                job.spec.comment_id,
+                serde_json::to_string(&job.spec)?,
+                job.repro_hash(),
+                JobState::Created.as_str(),
+                job.code,
+                job.script,
+            ],
+        )?;
+        tx.execute(
+            "INSERT INTO job_transitions (job_id, state, at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![job.spec.job_id, JobState::Created.as_str(), job.spec.created_at.to_rfc3339()],
+        )?;
+        for reply_to in std::iter::once(&job.spec.reply_to).chain(&job.spec.notify) {
+            tx.execute(
+                "INSERT OR IGNORE INTO job_targets (job_id, reply_to) VALUES (?1, ?2)",
+                rusqlite::params![job.spec.job_id, serde_json::to_string(reply_to)?],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_branch(&self, job_id: &str, branch: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.update("UPDATE jobs SET branch = ?2 WHERE id = ?1", rusqlite::params![job_id, branch])
+    }
+
+    fn set_check_run(&self, job_id: &str, check_run_id: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.update("UPDATE jobs SET check_run_id = ?2 WHERE id = ?1", rusqlite::params![job_id, check_run_id as i64])
+    }
+
+    fn add_target(&self, job_id: &str, reply_to: &ReplyTo) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let known: bool = conn.query_row("SELECT COUNT(*) > 0 FROM jobs WHERE id = ?1", rusqlite::params![job_id], |row| row.get(0))?;
+        if !known {
+            return Err("unknown job".into());
+        }
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO job_targets (job_id, reply_to) VALUES (?1, ?2)",
+            rusqlite::params![job_id, serde_json::to_string(reply_to)?],
+        )?;
+        Ok(inserted == 1)
+    }
+
+    fn set_status_message(&self, job_id: &str, reply_to: &ReplyTo, message_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.conn.lock().unwrap().execute(
+            "UPDATE job_targets SET status_message = ?3 WHERE job_id = ?1 AND reply_to = ?2",
+            rusqlite::params![job_id, serde_json::to_string(reply_to)?, message_id],
+        )? {
+            0 => Err("unknown job target".into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn transition(&self, job_id: &str, state: JobState, result: Option<&BisectResult>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let result = result.map(serde_json::to_string).transpose()?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE jobs SET state = ?2, result = COALESCE(?3, result) WHERE id = ?1",
+            rusqlite::params![job_id, state.as_str(), result],
+        )?;
+        if updated == 0 {
+            return Err("unknown job".into());
+        }
+        tx.execute(
+            "INSERT INTO job_transitions (job_id, state, at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![job_id, state.as_str(), Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get(&self, job_id: &str) -> Result<Option<JobRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.query("WHERE id = ?1", rusqlite::params![job_id])?.pop())
+    }
+
+    fn jobs_for(&self, reply_to: &ReplyTo, limit: usize) -> Result<Vec<JobRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        self.query(
+            "WHERE id IN (SELECT job_id FROM job_targets WHERE reply_to = ?1) ORDER BY rowid DESC LIMIT ?2",
+            rusqlite::params![serde_json::to_string(reply_to)?, limit as i64],
+        )
+    }
+
+    fn active_jobs(&self) -> Result<Vec<JobRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        self.query(
+            "WHERE state IN (?1, ?2, ?3) ORDER BY rowid",
+            rusqlite::params![JobState::Created.as_str(), JobState::Queued.as_str(), JobState::Running.as_str()],
+        )
+    }
+
+    fn attempts(&self, comment_id: &str) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.conn.lock().unwrap().query_row(
+            "SELECT COUNT(*) FROM jobs WHERE comment_id = ?1",
+            rusqlite::params![comment_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn first_delivery(&self, delivery_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let now = Utc::now();
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM deliveries WHERE received_at < ?1",
+            rusqlite::params![(now - chrono::Duration::days(DELIVERY_RETENTION_DAYS)).to_rfc3339()],
+        )?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO deliveries (id, received_at) VALUES (?1, ?2)",
+            rusqlite::params![delivery_id, now.to_rfc3339()],
+        )?;
+        Ok(inserted == 1)
+    }
+
+    fn forget_delivery(&self, delivery_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.conn.lock().unwrap().execute("DELETE FROM deliveries WHERE id = ?1", rusqlite::params![delivery_id])?;
+        Ok(())
+    }
+}
+
+impl SqliteStore {
+    /// Load all jobs matching `filter`, which is appended to the `SELECT` statement.
+    fn query(&self, filter: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<JobRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, spec, repro_hash, branch, check_run_id, state, result, code, script FROM jobs {}",
+            filter,
+        ))?;
+        let rows = stmt.query_map(params, |row| Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, Option<String>>(3)?,
+            row.get::<_, Option<i64>>(4)?,
+            row.get::<_, String>(5)?,
+            row.get::<_, Option<String>>(6)?,
+            row.get::<_, String>(7)?,
+            row.get::<_, Option<String>>(8)?,
+        )))?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut transitions = conn.prepare("SELECT state, at FROM job_transitions WHERE job_id = ?1 ORDER BY rowid")?;
+        let mut jobs = Vec::new();
+        let mut targets = conn.prepare("SELECT reply_to, status_message FROM job_targets WHERE job_id = ?1 ORDER BY rowid")?;
+        for (job_id, spec, repro_hash, branch, check_run_id, state, result, code, script) in rows {
+            let transitions = transitions.query_map(rusqlite::params![job_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+                .map(|row| {
+                    let (state, at) = row?;
+                    Ok((
+                        DateTime::parse_from_rfc3339(&at)?.with_timezone(&Utc),
+                        JobState::parse(&state).ok_or("invalid job state")?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, Box<dyn std::error::Error + Send + Sync>>>()?;
+            let targets = targets.query_map(rusqlite::params![job_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?
+                .map(|row| {
+                    let (reply_to, status_message) = row?;
+                    Ok(JobTarget { reply_to: serde_json::from_str(&reply_to)?, status_message })
+                })
+                .collect::<Result<Vec<_>, Box<dyn std::error::Error + Send + Sync>>>()?;
+            jobs.push(JobRecord {
+                spec: serde_json::from_str(&spec)?,
+                repro_hash,
+                branch,
+                check_run_id: check_run_id.map(|id| id as u64),
+                state: JobState::parse(&state).ok_or("invalid job state")?,
+                transitions,
+                result: result.map(|result| serde_json::from_str(&result)).transpose()?,
+                code,
+                script,
+                targets,
+            });
+        }
+        Ok(jobs)
+    }
+}
+
+#[test]
+fn test_sqlite_store() {
+    let store = SqliteStore::open(":memory:").unwrap();
+    let job = crate::job::test_job("gh1");
+    store.insert(&job).unwrap();
+    assert!(store.insert(&job).is_err());
+
+    let record = store.get("gh1").unwrap().unwrap();
+    assert_eq!(record.spec, job.spec);
+    assert_eq!(record.repro_hash, job.repro_hash());
+    assert_eq!(record.state, JobState::Created);
+    assert_eq!(record.branch, None);
+    assert_eq!(record.code, job.code);
+    assert_eq!(record.targets, [JobTarget { reply_to: job.spec.reply_to.clone(), status_message: None }]);
+
+    store.transition("gh1", JobState::Queued, None).unwrap();
+    store.set_branch("gh1", "job-gh1").unwrap();
+    store.set_check_run("gh1", 123).unwrap();
+    store.set_status_message("gh1", &job.spec.reply_to, "gh456").unwrap();
+    let zulip = ReplyTo::ZulipPrivate { user_id: 1 };
+    assert!(store.add_target("gh1", &zulip).unwrap());
+    assert!(!store.add_target("gh1", &zulip).unwrap());
+    assert!(store.set_status_message("gh1", &ReplyTo::ZulipPrivate { user_id: 2 }, "zulip1").is_err());
+    store.transition("gh1", JobState::Running, None).unwrap();
+    let result = BisectResult {
+        schema_version: 1,
+        status: crate::report::BisectStatus::NotFound,
+        exit_code: 0,
+        searched_nightlies: None,
+        regressed_toolchain: None,
+        searched_commits: None,
+        commit: None,
+        stderr_tail: String::new(),
+    };
+    store.transition("gh1", JobState::Completed, Some(&result)).unwrap();
+
+    let record = store.get("gh1").unwrap().unwrap();
+    assert_eq!(record.branch.as_deref(), Some("job-gh1"));
+    assert_eq!(record.check_run_id, Some(123));
+    assert_eq!(
+        record.targets,
+        [
+            JobTarget { reply_to: job.spec.reply_to.clone(), status_message: Some("gh456".to_string()) },
+            JobTarget { reply_to: zulip.clone(), status_message: None },
+        ],
+    );
+    assert_eq!(record.extra_targets(), [zulip]);
+    assert_eq!(record.state, JobState::Completed);
+    assert_eq!(record.result, Some(result));
+    assert_eq!(
+        record.transitions.iter().map(|(_, state)| *state).collect::<Vec<_>>(),
+        [JobState::Created, JobState::Queued, JobState::Running, JobState::Completed],
+    );
+
+    store.insert(&crate::job::test_job("gh2")).unwrap();
+    store.insert(&crate::job::test_job("gh3")).unwrap();
+    store.transition("gh3", JobState::Running, None).unwrap();
+    let ids = |jobs: Vec<JobRecord>| jobs.into_iter().map(|job| job.spec.job_id).collect::<Vec<_>>();
+    assert_eq!(ids(store.active_jobs().unwrap()), ["gh2", "gh3"]);
+    assert_eq!(ids(store.jobs_for(&job.spec.reply_to, 2).unwrap()), ["gh3", "gh2"]);
+    // gh1 reports to this thread too
+    assert_eq!(ids(store.jobs_for(&ReplyTo::ZulipPrivate { user_id: 1 }, 2).unwrap()), ["gh1"]);
+    assert_eq!(ids(store.jobs_for(&ReplyTo::ZulipPrivate { user_id: 2 }, 2).unwrap()), Vec::<String>::new());
+
+    assert_eq!(store.attempts("gh1").unwrap(), 1);
+    assert_eq!(store.attempts("gh4").unwrap(), 0);
+    assert!(store.first_delivery("72d3162e-cc78-11e3-81ab-4c9367dc0958").unwrap());
+    assert!(!store.first_delivery("72d3162e-cc78-11e3-81ab-4c9367dc0958").unwrap());
+    store.forget_delivery("72d3162e-cc78-11e3-81ab-4c9367dc0958").unwrap();
+    assert!(store.first_delivery("72d3162e-cc78-11e3-81ab-4c9367dc0958").unwrap());
+    store.conn.lock().unwrap().execute(
+        "INSERT INTO deliveries (id, received_at) VALUES ('old', ?1)",
+        rusqlite::params![(Utc::now() - chrono::Duration::days(30)).to_rfc3339()],
+    ).unwrap();
+    assert!(store.first_delivery("new").unwrap());
+    assert!(store.first_delivery("old").unwrap());
+
+    assert_eq!(store.get("gh4").unwrap(), None);
+    assert!(store.transition("gh4", JobState::Running, None).is_err());
+    assert!(store.set_branch("gh4", "job-gh4").is_err());
+    assert!(store.add_target("gh4", &job.spec.reply_to).is_err());
+}
+
+#[test]
+fn test_describe_job() {
+    let job = crate::job::test_job("gh1");
+    let created_at = job.spec.created_at;
+    let mut record = JobRecord {
+        spec: job.spec,
+        repro_hash: String::new(),
+        branch: Some("job-gh1".to_string()),
+        check_run_id: Some(123),
+        state: JobState::Running,
+        transitions: vec![
+            (created_at, JobState::Created),
+            (created_at + chrono::Duration::minutes(2), JobState::Running),
+        ],
+        result: None,
+        code: String::new(),
+        script: None,
+        targets: Vec::new(),
+    };
+    let now = created_at + chrono::Duration::minutes(7);
+    assert_eq!(
+        record.describe(now, None),
+        "- `gh1` requested 7 minutes ago by bjorn3: running for 5 minutes, \
+        https://github.com/bjorn3/cargo-bisect-rustc-bot-jobs/runs/123",
+    );
+
+    record.check_run_id = None;
+    let status = JobStatus::Completed { result: None, summary: String::new(), url: Some("https://example.com/1".to_string()) };
+    assert_eq!(
+        record.describe(now + chrono::Duration::hours(1), Some(&status)),
+        "- `gh1` requested 1 hour ago by bjorn3: completed 1 hour ago (result unknown), https://example.com/1",
+    );
+
+    assert_eq!(format_age(chrono::Duration::seconds(30)), "less than a minute");
+    assert_eq!(format_age(chrono::Duration::days(3)), "3 days");
+}
+
+#[test]
+fn test_sqlite_store_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("jobs.sqlite");
+    let path = path.to_str().unwrap();
+    SqliteStore::open(path).unwrap().insert(&crate::job::test_job("gh1")).unwrap();
+
+    // Reopening must not run the migrations again
+    let store = SqliteStore::open(path).unwrap();
+    assert_eq!(store.get("gh1").unwrap().unwrap().state, JobState::Created);
+    let version: usize = store.conn.lock().unwrap().query_row("PRAGMA user_version", rusqlite::params![], |row| row.get(0)).unwrap();
+    assert_eq!(version, MIGRATIONS.len());
+}
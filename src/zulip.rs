@@ -1,8 +1,8 @@
 async fn register_event_queue(client: &reqwest::Client) -> reqwest::Result<String> {
     let res = client
-        .post("https://rust-lang.zulipchat.com/api/v1/register?event_types=%5B%22message%22%5D&all_public_streams=true")
+        .post("https://rust-lang.zulipchat.com/api/v1/register?event_types=%5B%22message%22%2C%22update_message%22%5D&all_public_streams=true")
         .basic_auth(&*crate::ZULIP_USER, Some(&*crate::ZULIP_TOKEN))
-        .body("event_types=%5B%22message%22%5D&all_public_streams=true")
+        .body("event_types=%5B%22message%22%2C%22update_message%22%5D&all_public_streams=true")
         .send().await?;
     let res = match res.error_for_status_ref() {
         Ok(_) => res.text().await?,
@@ -41,18 +41,8 @@ pub(crate) async fn zulip_task() {
                 ZulipEvent::Heartbeat { id } => last_event_id = id as i64,
                 ZulipEvent::Message { id, message } => {
                     println!("{:?}", message);
-                    if let Some(stream_id) = message.stream_id {
-                        let _ = crate::parse_comment(
-                            &crate::ReplyTo::ZulipPublic { stream_id, subject: message.subject },
-                            &format!("zulip{}", message.id),
-                            &message.content,
-                        ).await;
-                    } else {
-                        let _ = crate::parse_comment(
-                            &crate::ReplyTo::ZulipPrivate { user_id: message.sender_id },
-                            &format!("zulip{}", message.id),
-                            &message.content,
-                        ).await;
+                    if let Err(err) = handle_message(message, false).await {
+                        println!("error: {}", err);
                     }
                     last_event_id = id as i64;
                 }
@@ -62,7 +52,20 @@ pub(crate) async fn zulip_task() {
                 ZulipEvent::UpdateMessageFlags { id } => last_event_id = id as i64,
                 ZulipEvent::RealUser { id } => last_event_id = id as i64,
                 ZulipEvent::Subscription { id } => last_event_id = id as i64,
-                ZulipEvent::UpdateMessage { id } => last_event_id = id as i64,
+                ZulipEvent::UpdateMessage { id, message_id, content } => {
+                    // Topic moves and other edits which don't touch the content don't matter
+                    if content.is_some() {
+                        println!("message {} edited", message_id);
+                        let res = match fetch_message(&client, message_id).await {
+                            Ok(message) => handle_message(message, true).await,
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = res {
+                            println!("error: {}", err);
+                        }
+                    }
+                    last_event_id = id as i64;
+                }
                 ZulipEvent::Reaction { id } => last_event_id = id as i64,
                 ZulipEvent::Other => {
                     println!("{:?}", events_json)
@@ -73,7 +76,45 @@ pub(crate) async fn zulip_task() {
     }
 }
 
-pub(crate) async fn zulip_post_public_message(stream_id: u64, subject: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_message(message: ZulipMessage, edited: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Never act on replies of the bot itself
+    if message.sender_email == *crate::ZULIP_USER {
+        return Ok(());
+    }
+    let reply_to = message.reply_to(&crate::ZULIP_USER);
+    let requester = crate::job::Requester::Zulip {
+        user_id: message.sender_id,
+        full_name: message.sender_full_name.clone(),
+    };
+    crate::parse_comment(
+        crate::context::global(),
+        &reply_to,
+        requester,
+        &format!("zulip{}", message.id),
+        &message.content,
+        edited,
+    ).await
+}
+
+/// Fetch the current version of a message. `update_message` events don't contain everything
+/// needed to handle the message.
+async fn fetch_message(client: &reqwest::Client, message_id: u64) -> Result<ZulipMessage, Box<dyn std::error::Error + Send + Sync>> {
+    #[derive(serde::Deserialize)]
+    struct MessageResponse {
+        message: ZulipMessage,
+    }
+
+    let res = client
+        .get(&format!("https://rust-lang.zulipchat.com/api/v1/messages/{}?apply_markdown=false", message_id))
+        .basic_auth(&*crate::ZULIP_USER, Some(&*crate::ZULIP_TOKEN))
+        .send().await?
+        .error_for_status()?
+        .text().await?;
+    Ok(serde_json::from_str::<MessageResponse>(&res)?.message)
+}
+
+/// Post a message to a stream, returning its id.
+pub(crate) async fn zulip_post_public_message(stream_id: u64, subject: &str, body: &str) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
     let client = reqwest::Client::new();
     let res = client
         .post(&format!(
@@ -86,24 +127,64 @@ pub(crate) async fn zulip_post_public_message(stream_id: u64, subject: &str, bod
         .send().await?
         .text().await?;
     println!("post message result: {}", res);
-    Ok(())
+    Ok(serde_json::from_str::<SentMessage>(&res)?.id)
 }
 
-pub(crate) async fn zulip_post_private_message(user_id: u64, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Send a private message to one or more users, returning its id.
+pub(crate) async fn zulip_post_private_message(user_ids: &[u64], body: &str) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
     let client = reqwest::Client::new();
     let res = client
         .post(&format!(
             "https://rust-lang.zulipchat.com/api/v1/messages?type=private&to=%5B{}%5D&content={}",
-            user_id,
+            user_ids.iter().map(|user_id| user_id.to_string()).collect::<Vec<_>>().join("%2C"),
             percent_encoding::utf8_percent_encode(body, percent_encoding::NON_ALPHANUMERIC),
         ))
         .basic_auth(&*crate::ZULIP_USER, Some(&*crate::ZULIP_TOKEN))
         .send().await?
         .text().await?;
     println!("post message result: {}", res);
+    Ok(serde_json::from_str::<SentMessage>(&res)?.id)
+}
+
+pub(crate) async fn zulip_edit_message(message_id: u64, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = reqwest::Client::new();
+    let res = client
+        .patch(&format!(
+            "https://rust-lang.zulipchat.com/api/v1/messages/{}?content={}",
+            message_id,
+            percent_encoding::utf8_percent_encode(body, percent_encoding::NON_ALPHANUMERIC),
+        ))
+        .basic_auth(&*crate::ZULIP_USER, Some(&*crate::ZULIP_TOKEN))
+        .send().await?
+        .error_for_status()?
+        .text().await?;
+    println!("edit message result: {}", res);
+    Ok(())
+}
+
+/// Response to sending a message. Failures don't contain an id and fail to parse.
+#[derive(serde::Deserialize)]
+struct SentMessage {
+    id: u64,
+}
+
+pub(crate) async fn zulip_add_reaction(message_id: u64, emoji_name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(&format!(
+            "https://rust-lang.zulipchat.com/api/v1/messages/{}/reactions?emoji_name={}",
+            message_id,
+            percent_encoding::utf8_percent_encode(emoji_name, percent_encoding::NON_ALPHANUMERIC),
+        ))
+        .basic_auth(&*crate::ZULIP_USER, Some(&*crate::ZULIP_TOKEN))
+        .send().await?
+        .error_for_status()?
+        .text().await?;
+    println!("add reaction result: {}", res);
     Ok(())
 }
 
+#[allow(dead_code)]
 #[derive(Debug, serde::Deserialize)]
 struct ZulipEvents {
     result: String,
@@ -149,6 +230,10 @@ enum ZulipEvent {
     #[serde(rename = "update_message")]
     UpdateMessage {
         id: u64,
+        message_id: u64,
+        /// The new content, if it was changed.
+        #[serde(default)]
+        content: Option<String>,
     },
     #[serde(rename = "reaction")]
     Reaction {
@@ -158,15 +243,108 @@ enum ZulipEvent {
     Other,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, serde::Deserialize)]
 struct ZulipMessage {
     id: u64,
     content: String,
     sender_full_name: String,
+    sender_email: String,
     sender_id: u64,
     #[serde(rename = "type")]
     type_: String, // private or stream
     #[serde(default)]
     stream_id: Option<u64>,
     subject: String,
+    display_recipient: DisplayRecipient,
+}
+
+/// The name of the stream for stream messages and everyone in the conversation, including the
+/// bot, for private messages.
+#[allow(dead_code)]
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum DisplayRecipient {
+    Stream(String),
+    Users(Vec<Recipient>),
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Recipient {
+    id: u64,
+    email: String,
+}
+
+impl ZulipMessage {
+    /// Where to reply to this message. `bot_email` is the email of the bot user, which is left out
+    /// of the recipients.
+    fn reply_to(&self, bot_email: &str) -> crate::ReplyTo {
+        if let Some(stream_id) = self.stream_id {
+            return crate::ReplyTo::ZulipPublic { stream_id, subject: self.subject.clone() };
+        }
+        let mut user_ids = match &self.display_recipient {
+            DisplayRecipient::Users(users) => users.iter()
+                .filter(|user| user.email != bot_email)
+                .map(|user| user.id)
+                .collect::<Vec<_>>(),
+            DisplayRecipient::Stream(_) => Vec::new(),
+        };
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        if user_ids.len() > 1 {
+            crate::ReplyTo::ZulipGroupPrivate { user_ids }
+        } else {
+            crate::ReplyTo::ZulipPrivate { user_id: self.sender_id }
+        }
+    }
+}
+
+#[test]
+fn test_parse_update_message_event() {
+    let events: ZulipEvents = serde_json::from_str(r#"{
+        "result": "success",
+        "events": [
+            {"type": "update_message", "id": 1, "message_id": 123, "user_id": 5, "content": "bisect-bot bisect end=2020-05-01", "rendered_content": "<p>x</p>"},
+            {"type": "update_message", "id": 2, "message_id": 124, "user_id": 5, "orig_subject": "a", "subject": "b"}
+        ]
+    }"#).unwrap();
+    match &events.events[..] {
+        [
+            ZulipEvent::UpdateMessage { id: 1, message_id: 123, content: Some(content) },
+            ZulipEvent::UpdateMessage { id: 2, message_id: 124, content: None },
+        ] => assert_eq!(content, "bisect-bot bisect end=2020-05-01"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[test]
+fn test_message_reply_to() {
+    let message = |display_recipient: serde_json::Value, stream_id: Option<u64>| -> ZulipMessage {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "content": "bisect-bot status",
+            "sender_full_name": "Alice",
+            "sender_email": "alice@example.com",
+            "sender_id": 5,
+            "type": if stream_id.is_some() { "stream" } else { "private" },
+            "stream_id": stream_id,
+            "subject": "",
+            "display_recipient": display_recipient,
+        })).unwrap()
+    };
+    let user = |id: u64, email: &str| serde_json::json!({ "id": id, "email": email, "full_name": email, "is_mirror_dummy": false });
+    let bot = "bisect-bot@zulipchat.com";
+
+    assert_eq!(
+        message(serde_json::json!("general"), Some(3)).reply_to(bot),
+        crate::ReplyTo::ZulipPublic { stream_id: 3, subject: String::new() },
+    );
+    assert_eq!(
+        message(serde_json::json!([user(5, "alice@example.com"), user(2, bot)]), None).reply_to(bot),
+        crate::ReplyTo::ZulipPrivate { user_id: 5 },
+    );
+    assert_eq!(
+        message(serde_json::json!([user(7, "bob@example.com"), user(5, "alice@example.com"), user(2, bot)]), None).reply_to(bot),
+        crate::ReplyTo::ZulipGroupPrivate { user_ids: vec![5, 7] },
+    );
 }